@@ -3,39 +3,38 @@ extern crate criterion;
 
 use criterion::Criterion;
 use rsstable::sst::SSTable;
-use simple_logger;
 fn test_sstable_performance(c: &mut Criterion) {
-    let mut sst = SSTable::new("./test_bench", 1);
+    let sst: SSTable = SSTable::new("./test_bench", 1);
     simple_logger::SimpleLogger::new()
         .with_level(log::LevelFilter::Info)
         .init()
         .unwrap();
-    sst.clear();
+    sst.clear().expect("failed to clear");
     // prepare
-    let disk_key = "1";
-    let mem_key = "999";
-    sst.insert(disk_key, disk_key);
-    sst.insert("hoge", "hoge");
-    sst.insert(mem_key, mem_key);
+    let disk_key = "1".to_string();
+    let mem_key = "999".to_string();
+    sst.insert(disk_key.clone(), disk_key.clone()).unwrap();
+    sst.insert("hoge".to_string(), "hoge".to_string()).unwrap();
+    sst.insert(mem_key.clone(), mem_key.clone()).unwrap();
 
     c.bench_function("sstable get from memtable", |b| {
         b.iter(|| {
-            sst.get(mem_key)
-                .expect(&format!("failed to get value by key {}", mem_key));
+            sst.get(&mem_key)
+                .unwrap_or_else(|| panic!("failed to get value by key {}", mem_key));
         })
     });
 
     c.bench_function("sstable get from disktable", |b| {
         b.iter(|| {
-            sst.get(disk_key)
-                .expect(&format!("failed to get value by key {}", disk_key));
+            sst.get(&disk_key)
+                .unwrap_or_else(|| panic!("failed to get value by key {}", disk_key));
         })
     });
 
     c.bench_function("sstable insert mem", |b| {
         b.iter(|| {
-            sst.insert(mem_key, mem_key)
-                .expect(&format!("failed to insert value by key {}", mem_key));
+            sst.insert(mem_key.clone(), mem_key.clone())
+                .unwrap_or_else(|_| panic!("failed to insert value by key {}", mem_key));
         })
     });
 
@@ -43,16 +42,41 @@ fn test_sstable_performance(c: &mut Criterion) {
         let mut disk = true;
         b.iter(|| {
             if disk {
-                sst.insert(disk_key, disk_key)
-                    .expect(&format!("failed to insert value by key {}", disk_key));
+                sst.insert(disk_key.clone(), disk_key.clone())
+                    .unwrap_or_else(|_| panic!("failed to insert value by key {}", disk_key));
             } else {
-                sst.insert(mem_key, mem_key)
-                    .expect(&format!("failed to insert value by key {}", mem_key));
+                sst.insert(mem_key.clone(), mem_key.clone())
+                    .unwrap_or_else(|_| panic!("failed to insert value by key {}", mem_key));
             }
             disk = !disk;
         })
     });
 }
 
-criterion_group!(benches, test_sstable_performance);
+// every write copies the memtable shared with a live range, which must not cost its size
+fn test_sstable_write_while_scanning(c: &mut Criterion) {
+    let sst: SSTable = SSTable::new("./test_bench_scan", 1_000_000);
+    sst.clear().expect("failed to clear");
+    (0..100_000).for_each(|i| {
+        sst.insert(format!("key-{}", i), format!("value-{}", i))
+            .unwrap();
+    });
+
+    c.bench_function("sstable insert mem while a range is held", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            let range = sst.range(..);
+            sst.insert(format!("key-{}", i % 100_000), "updated".to_string())
+                .unwrap_or_else(|_| panic!("failed to insert value by key {}", i));
+            drop(range);
+            i += 1;
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    test_sstable_performance,
+    test_sstable_write_while_scanning
+);
 criterion_main!(benches);
@@ -2,90 +2,438 @@
 //! Basically, this is a Key-Value store on top of local file storage.
 
 use log;
-use std::io;
+use std::{
+    collections::BTreeSet,
+    io,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
+mod background;
+mod batch;
+mod byte_utils;
+pub mod codec;
+pub mod compression;
 mod disktable;
+mod iter;
 mod memtable;
+pub mod options;
 mod rich_file;
+mod snapshot;
+mod transaction;
 
-pub struct SSTable {
-    // Sorted *String* Table :)
-    memtable: Box<dyn memtable::Memtable<Key = String, Value = String>>,
+pub use batch::WriteBatch;
+pub use codec::Codec;
+pub use compression::{BlockCodec, Compression};
+pub use disktable::FilterStats;
+pub use iter::{Cursor, Range};
+pub use memtable::{DroppedRecord, WalRecoveryReport};
+pub use options::{Options, SyncPolicy, WalRecoveryMode, WalRetention};
+pub use snapshot::Snapshot;
+use snapshot::{SeqNo, SnapshotList, LATEST};
+pub use transaction::{Transaction, TransactionConflict};
+
+/// `SSTable` can be shared among threads, e.g. by `Arc<SSTable>`.
+/// Reads run concurrently, while writes, flushes and compactions are serialized.
+/// Writes append to WAL before taking the lock readers share, so that reads don't wait for I/O.
+/// Full memtables are flushed, and generations are compacted, by background threads,
+/// which are shut down by `close` or on drop.
+pub struct SSTable<K = String, V = String> {
+    shared: Arc<background::Shared>,
+    workers: background::Workers,
+    max_immutable_memtables: usize,
+    wal_recovery_report: WalRecoveryReport,
+    snapshots: Arc<SnapshotList>,
+    codec: PhantomData<fn() -> (K, V)>,
+}
+
+// everything readers see, guarded by one lock.
+// a write changes it only after appending to WAL under `background::Shared::writer`.
+struct Tables {
+    // keys and values are kept encoded, so keys are sorted by their encoded bytes
+    memtable: Box<dyn memtable::Memtable<Key = Vec<u8>, Value = Vec<u8>>>,
     disktable: Box<dyn disktable::Disktable>,
+    // the sequence number of the last write
+    last_sequence: SeqNo,
 }
 
-impl SSTable {
-    pub fn new(dir_name: &str, mem_max_entry: usize) -> SSTable {
-        std::fs::create_dir_all(dir_name)
-            .expect(&format!("failed to create directory {}", dir_name));
-        SSTable {
-            memtable: Box::new(memtable::default::BTreeMemtable::new(
-                dir_name,
-                mem_max_entry,
-            )),
-            disktable: Box::new(disktable::default::FileDisktable::new(dir_name).unwrap()),
-        }
-    }
-    pub fn get(&self, key: impl Into<String>) -> Option<String> {
-        let key = key.into();
-        match self.memtable.get(&key) {
-            memtable::GetResult::Found(value) => Some(value.to_string()),
-            memtable::GetResult::Deleted => None,
-            memtable::GetResult::NotFound => self.disktable.find(&key),
-        }
-    }
-    pub fn insert(
-        &mut self,
-        key: impl Into<String>,
-        value: impl Into<String>,
-    ) -> Result<(), io::Error> {
-        let key = key.into();
-        let value = value.into();
-        self.memtable.set(key, value).on_flush(|mem| {
-            log::trace!(
-                "flush! memtable: {:?}, tombstones: {:?}",
-                mem.entries,
-                mem.tombstones
-            );
-            self.disktable.flush(mem)
+impl<K: Codec, V: Codec> SSTable<K, V> {
+    pub fn new(dir_name: &str, mem_max_entry: usize) -> SSTable<K, V> {
+        Self::with_options(
+            dir_name,
+            Options {
+                mem_max_entry: Some(mem_max_entry),
+                ..Default::default()
+            },
+        )
+    }
+    pub fn with_options(dir_name: &str, options: Options) -> SSTable<K, V> {
+        Self::open(dir_name, options)
+            .unwrap_or_else(|err| panic!("failed to open SSTable in {}: {:?}", dir_name, err))
+    }
+    /// Same as `with_options`, but returns an error instead of panicking
+    /// when WAL or data on disk can't be recovered.
+    pub fn open(dir_name: &str, options: Options) -> Result<SSTable<K, V>, io::Error> {
+        std::fs::create_dir_all(dir_name)?;
+        let snapshots = Arc::new(SnapshotList::default());
+        let (memtable, wal, wal_recovery_report) = memtable::default::BTreeMemtable::recover(
+            dir_name,
+            options.write_buffer_size,
+            options.mem_max_entry,
+            options.sync_policy,
+            options.wal_retention,
+            options.wal_recovery_mode,
+            snapshots.clone(),
+        )?;
+        let memtable: Box<dyn memtable::Memtable<Key = Vec<u8>, Value = Vec<u8>>> =
+            Box::new(memtable);
+        let disktable: Box<dyn disktable::Disktable> = Box::new(
+            disktable::default::FileDisktable::new(dir_name, &options, snapshots.clone())?,
+        );
+        let last_sequence = memtable.last_sequence().max(disktable.last_sequence());
+        let shared = Arc::new(background::Shared::new(
+            Tables {
+                memtable,
+                disktable,
+                last_sequence,
+            },
+            wal,
+        ));
+        let sync_interval = match options.sync_policy {
+            SyncPolicy::Interval(interval) => Some(interval),
+            _ => None,
+        };
+        Ok(SSTable {
+            workers: background::Workers::spawn(shared.clone(), sync_interval)?,
+            shared,
+            max_immutable_memtables: options.max_immutable_memtables,
+            wal_recovery_report,
+            snapshots,
+            codec: PhantomData,
         })
     }
+    /// The value of `key`, or `None` if it is absent or deleted.
+    ///
+    /// # Panics
+    /// Panics when data on disk turns out to be corrupted, or the value can't be decoded.
+    /// Use `try_get` to get an error instead.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.try_get(key)
+            .unwrap_or_else(|err| panic!("failed to get a value: {:?}", err))
+    }
+    /// Same as `get`, but returns an error instead of panicking
+    /// when data on disk turns out to be corrupted, or the value can't be decoded.
+    pub fn try_get(&self, key: &K) -> Result<Option<V>, io::Error> {
+        self.get_at(key, LATEST)
+    }
+    pub fn insert(&self, key: K, value: V) -> Result<(), io::Error> {
+        self.write_ops(vec![(key.encode(), Some(value.encode()))])
+    }
+
+    pub fn delete(&self, key: K) -> Result<(), io::Error> {
+        self.write_ops(vec![(key.encode(), None)])
+    }
 
-    pub fn delete(&mut self, key: impl Into<String>) -> () {
-        self.memtable.delete(key.into());
+    /// Applies all puts and deletes in `batch` atomically.
+    /// A flush triggered by the batch happens only after the whole batch is applied.
+    pub fn write(&self, batch: WriteBatch<K, V>) -> Result<(), io::Error> {
+        self.write_ops(batch.into_ops())
     }
 
-    pub fn clear(&mut self) -> Result<(), io::Error> {
-        self.disktable.clear()?;
-        self.memtable.clear();
+    fn write_ops(&self, ops: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> io::Result<()> {
+        self.shared.check_error()?;
+        let mut wal = self.shared.writer.lock().unwrap();
+        let queued = self.apply(&mut wal, ops)?;
+        drop(wal);
+        self.after_write(queued);
         Ok(())
     }
+
+    // appends `ops` to WAL, and then applies them to the memtable.
+    // returns whether the memtable got full and was queued for flush.
+    // an error is returned only if `ops` are not applied.
+    fn apply(
+        &self,
+        wal: &mut memtable::WriteAheadLog,
+        ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    ) -> io::Result<bool> {
+        if ops.is_empty() {
+            return Ok(false);
+        }
+        // only writers change it, and they hold `wal`
+        let first_seq = self.shared.tables.read().unwrap().last_sequence + 1;
+        match ops.as_slice() {
+            [(key, Some(value))] => wal.insert((key, value), first_seq)?,
+            [(key, None)] => wal.delete(key, first_seq)?,
+            _ => {
+                let refs: Vec<_> = ops
+                    .iter()
+                    .map(|(key, value)| (key.as_slice(), value.as_deref()))
+                    .collect();
+                wal.write_batch(first_seq, &refs)?;
+            }
+        }
+        let full = {
+            let mut tables = self.shared.tables.write().unwrap();
+            tables.last_sequence = first_seq + ops.len() as SeqNo - 1;
+            tables.memtable.write(ops, first_seq);
+            tables.memtable.is_full()
+        };
+        if !full {
+            return Ok(false);
+        }
+        // the write is applied already, so a failed rotation doesn't fail it.
+        // the memtable keeps the entries, and the next write fails if WAL is broken,
+        // or tries to rotate it again otherwise.
+        let sealed = match wal.rotate() {
+            Ok(sealed) => sealed,
+            Err(err) => {
+                log::error!("failed to rotate WAL: {:?}", err);
+                return Ok(false);
+            }
+        };
+        let mut tables = self.shared.tables.write().unwrap();
+        let memtable = tables.memtable.flush(sealed);
+        log::trace!("flush! memtable: {:?}", memtable.entries.entries);
+        tables.disktable.schedule_flush(memtable);
+        Ok(true)
+    }
+
+    // hands a memtable queued by a write to the flush thread
+    fn after_write(&self, queued: bool) {
+        if !queued {
+            return;
+        }
+        self.shared.request_flush();
+        // the writer waits for the flush thread to catch up, not to pile up memtables in memory.
+        // a background error doesn't fail the write applied already,
+        // but fails the next one before it is applied, and is reported by `close`.
+        let _ = self.shared.wait_for_flushes(self.max_immutable_memtables);
+    }
+
+    /// Blocks until every memtable queued for flush so far is written to disk.
+    pub fn wait_for_flushes(&self) -> Result<(), io::Error> {
+        self.shared.wait_for_flushes(0)
+    }
+
+    /// A consistent view of the entries at this moment, which later writes don't change.
+    pub fn snapshot(&self) -> Snapshot {
+        // taken under the lock, so that no flush or compaction drops what it needs meanwhile
+        let tables = self.shared.tables.read().unwrap();
+        Snapshot::new(tables.last_sequence, &self.snapshots)
+    }
+
+    /// Starts a transaction which reads from a snapshot at this moment,
+    /// and buffers its writes until `Transaction::commit`.
+    pub fn begin_transaction(&self) -> Transaction<K, V> {
+        Transaction::new(self.snapshot())
+    }
+
+    /// Iterates over live entries within `range` in key order.
+    /// Keys are compared by their encoded bytes.
+    pub fn range(&self, range: impl RangeBounds<K>) -> Range<K, V> {
+        self.range_at(range, LATEST)
+    }
+
+    /// Iterates over live entries whose encoded keys start with `prefix` in key order.
+    pub fn scan_prefix(&self, prefix: impl AsRef<[u8]>) -> Range<K, V> {
+        let prefix = prefix.as_ref();
+        // the smallest key larger than any key with the prefix
+        let end = match prefix.iter().rposition(|byte| *byte != u8::MAX) {
+            Some(pos) => {
+                let mut end = prefix[..=pos].to_vec();
+                end[pos] += 1;
+                Bound::Excluded(end)
+            }
+            None => Bound::Unbounded,
+        };
+        self.range_encoded(Bound::Included(prefix.to_vec()), end, LATEST)
+    }
+
+    /// A cursor over live entries, which moves in both directions.
+    pub fn cursor(&self) -> Cursor<K, V> {
+        self.cursor_at(LATEST)
+    }
+
+    // reads below see only the writes whose sequence numbers are equal to or less than `seq`
+
+    pub(crate) fn get_at(&self, key: &K, seq: SeqNo) -> io::Result<Option<V>> {
+        let value = self.find(&key.encode(), seq)?.and_then(|(_, value)| value);
+        value.map(|value| V::decode(&value)).transpose()
+    }
+
+    // the sequence number and the value of the newest version of `key` not newer than `seq`,
+    // where a tombstone has no value. data files are read after the lock is released.
+    fn find(&self, key: &[u8], seq: SeqNo) -> io::Result<Option<(SeqNo, Option<Vec<u8>>)>> {
+        let (memtable, disktable) = {
+            let tables = self.shared.tables.read().unwrap();
+            (tables.memtable.entries(), tables.disktable.lookup(key))
+        };
+        match memtable.find(&key.to_vec(), seq) {
+            Some((_, seq, value)) => Ok(Some((seq, value.cloned()))),
+            None => Ok(disktable
+                .find(key, seq)?
+                .map(|entry| (entry.seq, entry.value))),
+        }
+    }
+
+    // applies `batch` only if none of `keys` was written after `seq`,
+    // so that no other write can slip in between the check and the write
+    pub(crate) fn write_unless_modified(
+        &self,
+        keys: &BTreeSet<Vec<u8>>,
+        seq: SeqNo,
+        batch: WriteBatch<K, V>,
+    ) -> io::Result<()> {
+        self.shared.check_error()?;
+        let mut wal = self.shared.writer.lock().unwrap();
+        for key in keys {
+            let key_seq = self.find(key, LATEST)?.map(|(seq, _)| seq);
+            if key_seq > Some(seq) {
+                return Err(transaction::conflict(key));
+            }
+        }
+        let queued = self.apply(&mut wal, batch.into_ops())?;
+        drop(wal);
+        self.after_write(queued);
+        Ok(())
+    }
+
+    pub(crate) fn range_at(&self, range: impl RangeBounds<K>, seq: SeqNo) -> Range<K, V> {
+        self.range_encoded(
+            range.start_bound().map(K::encode),
+            range.end_bound().map(K::encode),
+            seq,
+        )
+    }
+
+    pub(crate) fn cursor_at(&self, seq: SeqNo) -> Cursor<K, V> {
+        let tables = self.shared.tables.read().unwrap();
+        let mut sources: Vec<Box<dyn iter::RawCursor + Send>> =
+            vec![Box::new(tables.memtable.entries())];
+        sources.extend(tables.disktable.cursors());
+        Cursor::new(sources, seq)
+    }
+
+    fn range_encoded(&self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>, seq: SeqNo) -> Range<K, V> {
+        let is_empty = match (&start, &end) {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (Bound::Included(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end))
+            | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+            _ => false,
+        };
+        if is_empty {
+            return Range::empty();
+        }
+        let tables = self.shared.tables.read().unwrap();
+        let mem_entries = tables
+            .memtable
+            .entries()
+            .shared_range(start.as_ref(), end.as_ref())
+            .map(|(key, seq, value)| Ok(disktable::DataEntry::new(key, seq, value)));
+        let disk_entries = tables.disktable.range(
+            start.as_ref().map(Vec::as_slice),
+            end.as_ref().map(Vec::as_slice),
+        );
+        let sources: Vec<iter::EntryIter> = vec![Box::new(mem_entries), disk_entries];
+        Range::new(Box::new(disktable::MergingIter::new(sources)), end, seq)
+    }
+
+    /// Merges all data generations on disk into one, after memtables waiting for flush are flushed.
+    /// The compaction runs on the background thread, and this waits for it.
+    /// Fails while background work is paused.
+    pub fn compact(&self) -> Result<(), io::Error> {
+        self.wait_for_flushes()?;
+        self.shared.compact_all()
+    }
+
+    /// Blocks until no compaction is running or due, unless background work is paused.
+    pub fn wait_for_compactions(&self) -> Result<(), io::Error> {
+        self.shared.wait_for_compactions()
+    }
+
+    /// Stops starting compactions, and waits for the running one to finish.
+    /// Flushes go on, so that writes never wait for `resume_background_work`.
+    /// Calls nest, and each of them needs a `resume_background_work`.
+    pub fn pause_background_work(&self) {
+        self.shared.pause(false);
+    }
+
+    /// Lets compactions run again, including ones which came due while paused.
+    pub fn resume_background_work(&self) {
+        self.shared.resume();
+    }
+
+    /// Shuts down background threads, after memtables waiting for flush are flushed.
+    /// A running compaction is abandoned, and runs again after reopening.
+    /// Same as dropping, but tells whether background work has failed.
+    pub fn close(mut self) -> Result<(), io::Error> {
+        self.workers.shutdown();
+        self.shared.check_error()
+    }
+
+    /// Makes all writes so far durable regardless of `Options::sync_policy`.
+    pub fn sync(&self) -> Result<(), io::Error> {
+        self.shared.writer.lock().unwrap().sync()
+    }
+
+    /// What was recovered from WAL, and what was dropped, at open.
+    pub fn wal_recovery_report(&self) -> &WalRecoveryReport {
+        &self.wal_recovery_report
+    }
+
+    /// Statistics of Bloom filters consulted by `get`.
+    pub fn filter_stats(&self) -> FilterStats {
+        self.shared.tables.read().unwrap().disktable.filter_stats()
+    }
+
+    pub fn clear(&self) -> Result<(), io::Error> {
+        // a running compaction is abandoned, not to write generations being removed
+        self.shared.pause(true);
+        let result = {
+            let mut wal = self.shared.writer.lock().unwrap();
+            let mut tables = self.shared.tables.write().unwrap();
+            tables.disktable.clear().and_then(|_| wal.clear()).map(|_| {
+                tables.memtable.clear();
+            })
+        };
+        self.shared.resume();
+        // memtables waiting for flush are dropped as well
+        self.shared.notify_flushed();
+        result
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::sst::SSTable;
+    use crate::sst::{
+        options::{CompactionStrategy, LeveledOptions},
+        BlockCodec, Codec, Compression, Options, SSTable, SyncPolicy, TransactionConflict,
+        WalRecoveryMode, WalRetention, WriteBatch,
+    };
     #[test]
     fn test_sstable() {
         let key = |i| format!("key-{}", i);
         let value = |i| format!("value-{}", i);
 
-        let mut sst = SSTable::new("./test_tmp", 200);
+        let sst = SSTable::new("./test_tmp", 200);
         assert!(sst.clear().is_ok());
         // get -> set -> get
         (1..300).for_each(|i| {
-            assert_eq!(sst.get(key(i)), None);
+            assert_eq!(sst.get(&key(i)), None);
             sst.insert(key(i), value(i)).expect("success");
-            assert_eq!(sst.get(key(i)), Some(value(i)));
+            assert_eq!(sst.get(&key(i)), Some(value(i)));
         });
         // get -> delete -> get
         (1..300).for_each(|i| {
-            assert_eq!(sst.get(key(i)), Some(value(i)));
-            sst.delete(key(i));
-            assert_eq!(sst.get(key(i)), None);
+            assert_eq!(sst.get(&key(i)), Some(value(i)));
+            sst.delete(key(i)).expect("success");
+            assert_eq!(sst.get(&key(i)), None);
         });
         // get
         (1..300).for_each(|i| {
-            assert_eq!(sst.get(key(i)), None);
+            assert_eq!(sst.get(&key(i)), None);
         });
     }
 
@@ -93,19 +441,1413 @@ mod tests {
     fn test_sstabl_tombstones() {
         let key = |i| format!("key-{}", i);
         let value = |i| format!("value-{}", i);
-        let mut sst = SSTable::new("./test_tmp2", 3);
+        let sst = SSTable::new("./test_tmp2", 3);
         assert!(sst.clear().is_ok());
         (1..=5).for_each(|i| {
             sst.insert(key(i), value(i)).expect("success");
         });
-        sst.delete(key(2));
+        sst.delete(key(2)).expect("success");
+        drop(sst);
         // restore WAL
         // memtable: [4, 5], tombstone: [2], disktable: [1, 2, 3]
         let sst = SSTable::new("./test_tmp2", 3);
-        assert_eq!(sst.get(key(1)), Some(value(1)));
-        assert_eq!(sst.get(key(2)), None);
-        assert_eq!(sst.get(key(3)), Some(value(3)));
-        assert_eq!(sst.get(key(4)), Some(value(4)));
-        assert_eq!(sst.get(key(5)), Some(value(5)));
+        assert_eq!(sst.get(&key(1)), Some(value(1)));
+        assert_eq!(sst.get(&key(2)), None);
+        assert_eq!(sst.get(&key(3)), Some(value(3)));
+        assert_eq!(sst.get(&key(4)), Some(value(4)));
+        assert_eq!(sst.get(&key(5)), Some(value(5)));
+    }
+
+    #[test]
+    fn test_sstable_tombstones_survive_flush() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let sst = SSTable::new("./test_tmp3", 3);
+        assert!(sst.clear().is_ok());
+        // disktable: [1, 2, 3, 4]
+        (1..=4).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        sst.delete(key(2)).expect("success");
+        // disktable: [1, 2, 3, 4], [2(deleted), 5, 6, 7, 8]
+        (5..=8).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        assert_eq!(sst.get(&key(2)), None);
+
+        drop(sst);
+        let sst = SSTable::new("./test_tmp3", 3);
+        assert_eq!(sst.get(&key(1)), Some(value(1)));
+        assert_eq!(sst.get(&key(2)), None);
+        assert_eq!(sst.get(&key(8)), Some(value(8)));
+    }
+
+    fn count_data_files(dir_name: &str) -> usize {
+        std::fs::read_dir(dir_name)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with("data_")
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_sstable_compaction() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let options = || Options {
+            mem_max_entry: Some(3),
+            compaction_threshold: None,
+            ..Default::default()
+        };
+        let sst = SSTable::with_options("./test_tmp4", options());
+        assert!(sst.clear().is_ok());
+        (1..=20).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        (1..=20).filter(|i| i % 3 == 0).for_each(|i| {
+            sst.delete(key(i)).expect("success");
+        });
+        sst.insert(key(1), value(100)).expect("success");
+        (21..=24).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        assert!(count_data_files("./test_tmp4") > 1);
+
+        sst.compact().expect("success");
+        assert_eq!(count_data_files("./test_tmp4"), 1);
+        drop(sst);
+        let sst = SSTable::with_options("./test_tmp4", options());
+        assert_eq!(sst.get(&key(1)), Some(value(100)));
+        (2..=24).for_each(|i| {
+            let expected = if i % 3 == 0 && i <= 20 {
+                None
+            } else {
+                Some(value(i))
+            };
+            assert_eq!(sst.get(&key(i)), expected);
+        });
+    }
+
+    #[test]
+    fn test_sstable_auto_compaction() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let sst = SSTable::with_options(
+            "./test_tmp5",
+            Options {
+                mem_max_entry: Some(3),
+                compaction_threshold: Some(3),
+                ..Default::default()
+            },
+        );
+        assert!(sst.clear().is_ok());
+        (1..=100).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+            sst.wait_for_flushes().expect("success");
+            sst.wait_for_compactions().expect("success");
+            assert!(count_data_files("./test_tmp5") < 3);
+        });
+        (1..=100).for_each(|i| {
+            assert_eq!(sst.get(&key(i)), Some(value(i)));
+        });
+    }
+
+    #[test]
+    fn test_sstable_leveled_compaction() {
+        let key = |i| format!("key-{:03}", i);
+        let value = |i| format!("value-{}", i);
+        let options = || Options {
+            mem_max_entry: Some(5),
+            compaction_threshold: Some(2),
+            compaction_strategy: CompactionStrategy::Leveled(LeveledOptions {
+                max_bytes_for_level_base: 256,
+                max_bytes_for_level_multiplier: 2,
+                target_file_size: 128,
+            }),
+            ..Default::default()
+        };
+        let sst = SSTable::with_options("./test_tmp6", options());
+        assert!(sst.clear().is_ok());
+        (0..300).map(|i| (i * 37) % 300).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        (0..300).filter(|i| i % 4 == 0).for_each(|i| {
+            sst.delete(key(i)).expect("success");
+        });
+        (300..320).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+
+        let assert_all = |sst: &SSTable| {
+            (0..320).for_each(|i| {
+                let expected = if i % 4 == 0 && i < 300 {
+                    None
+                } else {
+                    Some(value(i))
+                };
+                assert_eq!(sst.get(&key(i)), expected, "key: {}", key(i));
+            });
+        };
+        assert_all(&sst);
+        drop(sst);
+        let sst = SSTable::with_options("./test_tmp6", options());
+        assert_all(&sst);
+        sst.compact().expect("success");
+        assert_all(&sst);
+    }
+
+    #[test]
+    fn test_sstable_bloom_filter() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let options = || Options {
+            mem_max_entry: Some(50),
+            compaction_threshold: None,
+            bloom_bits_per_key: Some(10),
+            ..Default::default()
+        };
+        let sst = SSTable::with_options("./test_tmp7", options());
+        assert!(sst.clear().is_ok());
+        (0..500).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+
+        drop(sst);
+        // filters are loaded at open
+        let sst = SSTable::with_options("./test_tmp7", options());
+        (0..450).for_each(|i| {
+            assert_eq!(sst.get(&key(i)), Some(value(i)));
+        });
+        (500..1500).for_each(|i| {
+            assert_eq!(sst.get(&key(i)), None);
+        });
+        let stats = sst.filter_stats();
+        assert!(stats.checked > 0);
+        assert!(stats.skipped > 0);
+        assert!(
+            stats.false_positive_rate() < 0.05,
+            "false positive rate: {}",
+            stats.false_positive_rate()
+        );
+
+        // a key stored only in versions newer than the read is not a false positive.
+        // new keys sort after the others, so only generations holding them are looked up.
+        let snapshot = sst.snapshot();
+        (0..=50).for_each(|i| {
+            sst.insert(format!("zz-{:02}", i), value(i))
+                .expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        let false_positives = sst.filter_stats().false_positives;
+        (0..=50).for_each(|i| {
+            assert_eq!(snapshot.get(&sst, &format!("zz-{:02}", i)).unwrap(), None);
+        });
+        assert_eq!(sst.filter_stats().false_positives, false_positives);
+    }
+
+    #[test]
+    fn test_sstable_detects_corruption() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let options = || Options {
+            mem_max_entry: Some(3),
+            compaction_threshold: None,
+            ..Default::default()
+        };
+        // file names below depend on generation numbers, which even `clear` doesn't reset
+        let _ = std::fs::remove_dir_all("./test_tmp8");
+        let sst = SSTable::with_options("./test_tmp8", options());
+        // disktable: [1, 2, 3, 4], [5, 6, 7, 8]
+        (1..=8).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        assert_eq!(sst.try_get(&key(1)).unwrap(), Some(value(1)));
+
+        // flip a bit in the first entry of data_1
+        let path = std::path::Path::new("./test_tmp8").join("data_1");
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[20] ^= 0x01;
+        std::fs::write(&path, &bytes).unwrap();
+        let err = sst.try_get(&key(1)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        // other generations are still readable
+        assert_eq!(sst.try_get(&key(5)).unwrap(), Some(value(5)));
+
+        // truncate data_2
+        let path = std::path::Path::new("./test_tmp8").join("data_2");
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..10]).unwrap();
+        let err = sst.try_get(&key(8)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(sst.compact().is_err());
+    }
+
+    #[test]
+    fn test_sstable_block_format() {
+        let key = |i| format!("tenant/{:03}/user/{:05}", i % 7, i);
+        let value = |i| format!("value-{}", i);
+        let options = || Options {
+            mem_max_entry: Some(500),
+            compaction_threshold: None,
+            bloom_bits_per_key: None,
+            block_size: 256,
+            block_restart_interval: 4,
+            ..Default::default()
+        };
+        let sst = SSTable::with_options("./test_tmp9", options());
+        assert!(sst.clear().is_ok());
+        (0..1000).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        (0..1000).step_by(3).for_each(|i| {
+            sst.delete(key(i)).expect("success");
+        });
+        sst.compact().expect("success");
+
+        drop(sst);
+        let sst = SSTable::with_options("./test_tmp9", options());
+        (0..1000).for_each(|i| {
+            let expected = if i % 3 == 0 { None } else { Some(value(i)) };
+            assert_eq!(sst.try_get(&key(i)).unwrap(), expected);
+        });
+        // keys between or around the stored ones
+        assert_eq!(sst.get(&"tenant/000/user/00000a".to_string()), None);
+        assert_eq!(sst.get(&"tenant/".to_string()), None);
+        assert_eq!(sst.get(&"tenant/999".to_string()), None);
+
+        // shared prefixes are not stored repeatedly.
+        // the data files hold nearly every inserted value, as deletes count toward the flush
+        // threshold but only the first ones get flushed, and the rest stay in the memtable.
+        let raw_size: usize = (0..1000).map(|i| key(i).len() + value(i).len()).sum();
+        let data_size: u64 = std::fs::read_dir("./test_tmp9")
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("data_"))
+            .map(|entry| entry.metadata().unwrap().len())
+            .sum();
+        assert!(data_size < raw_size as u64);
+    }
+
+    // reverses bytes of a block, which is enough to tell whether it is used
+    struct ReverseCodec;
+    impl BlockCodec for ReverseCodec {
+        fn id(&self) -> u8 {
+            42
+        }
+        fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+            Ok(data.iter().rev().copied().collect())
+        }
+        fn decompress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+            Ok(data.iter().rev().copied().collect())
+        }
+    }
+
+    #[test]
+    fn test_sstable_compression() {
+        let key = |i| format!("key-{:04}", i);
+        let value = |i| {
+            format!(
+                "{{\"id\": {}, \"name\": \"user\", \"tags\": [\"a\", \"b\"]}}",
+                i
+            )
+        };
+        let options = |compression| Options {
+            mem_max_entry: Some(100),
+            compaction_threshold: None,
+            compression,
+            ..Default::default()
+        };
+        let data_size = |dir_name| -> u64 {
+            std::fs::read_dir(dir_name)
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("data_"))
+                .map(|entry| entry.metadata().unwrap().len())
+                .sum()
+        };
+        let mut sizes = vec![];
+        for (dir_name, compression) in [
+            ("./test_tmp10", Compression::None),
+            ("./test_tmp11", Compression::Snappy),
+        ] {
+            let sst = SSTable::with_options(dir_name, options(compression));
+            assert!(sst.clear().is_ok());
+            (0..1000).for_each(|i| {
+                sst.insert(key(i), value(i)).expect("success");
+            });
+            sizes.push(data_size(dir_name));
+        }
+        assert!(sizes[1] < sizes[0]);
+
+        // generations compressed by each codec are readable together
+        let sst = SSTable::with_options(
+            "./test_tmp11",
+            options(Compression::Custom(std::sync::Arc::new(ReverseCodec))),
+        );
+        (1000..1200).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        (0..1200).for_each(|i| assert_eq!(sst.get(&key(i)), Some(value(i))));
+        sst.compact().expect("success");
+        drop(sst);
+        let sst = SSTable::with_options(
+            "./test_tmp11",
+            options(Compression::Custom(std::sync::Arc::new(ReverseCodec))),
+        );
+        (0..1200).for_each(|i| assert_eq!(sst.get(&key(i)), Some(value(i))));
+    }
+
+    #[test]
+    fn test_sstable_manifest() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let options = || Options {
+            mem_max_entry: Some(3),
+            compaction_threshold: None,
+            ..Default::default()
+        };
+        let dir = std::path::Path::new("./test_tmp12");
+        // file names below depend on generation numbers, which even `clear` doesn't reset
+        let _ = std::fs::remove_dir_all("./test_tmp12");
+        let sst = SSTable::with_options("./test_tmp12", options());
+        // disktable: [1, 2, 3, 4], [5, 6, 7, 8]
+        (1..=8).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        sst.compact().expect("success");
+        // generation numbers are never reused even though data_1 and data_2 are gone
+        assert_eq!(count_data_files("./test_tmp12"), 1);
+        assert!(dir.join("data_3").exists());
+
+        // a data file written by an interrupted flush is not recorded in MANIFEST
+        std::fs::copy(dir.join("data_3"), dir.join("data_10")).unwrap();
+        // a record torn at the tail of MANIFEST
+        let mut manifest = std::fs::read(dir.join("MANIFEST")).unwrap();
+        manifest.extend_from_slice(&[100, 0, 0, 0, 1, 2]);
+        std::fs::write(dir.join("MANIFEST"), &manifest).unwrap();
+
+        drop(sst);
+        let sst = SSTable::with_options("./test_tmp12", options());
+        // files are kept when replay stops at a torn record, and removed at the next open
+        assert!(dir.join("data_10").exists());
+        (1..=8).for_each(|i| assert_eq!(sst.get(&key(i)), Some(value(i))));
+        (9..=12).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        assert!(dir.join("data_4").exists());
+        drop(sst);
+        let sst: SSTable<String, String> = SSTable::with_options("./test_tmp12", options());
+        assert!(!dir.join("data_10").exists());
+
+        // nor does clear reuse generation numbers
+        assert!(sst.clear().is_ok());
+        assert_eq!(count_data_files("./test_tmp12"), 0);
+        (13..=16).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        assert!(dir.join("data_5").exists());
+        drop(sst);
+
+        // a generation recorded in MANIFEST but missing on disk
+        std::fs::remove_file(dir.join("data_5")).unwrap();
+        let result =
+            std::panic::catch_unwind(|| SSTable::<String>::with_options("./test_tmp12", options()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_sstable_manifest_broken_in_the_middle() {
+        let key = |i| format!("key-{:02}", i);
+        let options = || Options {
+            mem_max_entry: Some(3),
+            compaction_threshold: None,
+            ..Default::default()
+        };
+        let dir = std::path::Path::new("./test_tmp30");
+        let sst = SSTable::with_options("./test_tmp30", options());
+        assert!(sst.clear().is_ok());
+        (0..20).for_each(|i| {
+            sst.insert(key(i), i.to_string()).expect("success");
+        });
+        sst.close().expect("success");
+        let data_files = count_data_files("./test_tmp30");
+        assert!(data_files > 0);
+
+        // a length of the first record pointing past the end of MANIFEST,
+        // which is not a torn tail as more records follow
+        let mut manifest = std::fs::read(dir.join("MANIFEST")).unwrap();
+        manifest[3] ^= 1;
+        std::fs::write(dir.join("MANIFEST"), &manifest).unwrap();
+        let err = SSTable::<String>::open("./test_tmp30", options())
+            .err()
+            .expect("failure");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(count_data_files("./test_tmp30"), data_files);
+        assert_eq!(std::fs::read(dir.join("MANIFEST")).unwrap(), manifest);
+    }
+
+    fn wal_segments(dir_name: &str) -> Vec<std::path::PathBuf> {
+        let mut segments: Vec<_> = std::fs::read_dir(dir_name)
+            .map(|dir| dir.map(|entry| entry.unwrap().path()).collect())
+            .unwrap_or_default();
+        segments.retain(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            name.starts_with("wal_") && name.ends_with(".log")
+        });
+        segments.sort();
+        segments
+    }
+
+    #[test]
+    fn test_sstable_binary_wal() {
+        let key = |i| format!("key\n{}\0", i);
+        let value = |i| format!("value\0{}\n\n", i);
+        let sst = SSTable::new("./test_tmp13", 100);
+        assert!(sst.clear().is_ok());
+        (1..=5).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        sst.delete(key(2)).expect("success");
+        sst.insert(key(6), value(6)).expect("success");
+
+        drop(sst);
+        // restore WAL
+        let sst = SSTable::new("./test_tmp13", 100);
+        assert_eq!(sst.get(&key(1)), Some(value(1)));
+        assert_eq!(sst.get(&key(2)), None);
+        assert_eq!(sst.get(&key(6)), Some(value(6)));
+
+        drop(sst);
+        // the last record is torn
+        let sst = SSTable::new("./test_tmp13", 100);
+        (7..=9).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        let path = wal_segments("./test_tmp13").pop().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..(bytes.len() - 3)]).unwrap();
+        drop(sst);
+        let sst = SSTable::new("./test_tmp13", 100);
+        assert_eq!(sst.get(&key(8)), Some(value(8)));
+        assert_eq!(sst.get(&key(9)), None);
+    }
+
+    #[test]
+    fn test_sstable_sync_policy() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let policies = [
+            SyncPolicy::None,
+            SyncPolicy::EveryWrite,
+            SyncPolicy::EveryN(3),
+            SyncPolicy::Interval(std::time::Duration::from_millis(1)),
+        ];
+        for sync_policy in policies {
+            let options = || Options {
+                mem_max_entry: Some(100),
+                sync_policy,
+                ..Default::default()
+            };
+            let sst = SSTable::with_options("./test_tmp14", options());
+            assert!(sst.clear().is_ok());
+            (1..=10).for_each(|i| {
+                sst.insert(key(i), value(i)).expect("success");
+            });
+            sst.delete(key(3)).expect("success");
+            sst.sync().expect("success");
+
+            drop(sst);
+            let sst = SSTable::with_options("./test_tmp14", options());
+            assert_eq!(sst.get(&key(1)), Some(value(1)), "{:?}", sync_policy);
+            assert_eq!(sst.get(&key(3)), None, "{:?}", sync_policy);
+            assert_eq!(sst.get(&key(10)), Some(value(10)), "{:?}", sync_policy);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_sstable_wal_write_error() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let options = || Options {
+            mem_max_entry: Some(3),
+            compaction_threshold: None,
+            sync_policy: SyncPolicy::EveryWrite,
+            ..Default::default()
+        };
+        let dir = std::path::Path::new("./test_tmp32");
+        let sst = SSTable::with_options("./test_tmp32", options());
+        assert!(sst.clear().is_ok());
+        // the segment started by the next flush fails every write with ENOSPC
+        let segment = wal_segments("./test_tmp32").pop().unwrap();
+        let name = segment.file_name().unwrap().to_string_lossy().to_string();
+        let number: u64 = name["wal_".len()..(name.len() - ".log".len())]
+            .parse()
+            .unwrap();
+        let next = dir.join(format!("wal_{:06}.log", number + 1));
+        std::os::unix::fs::symlink("/dev/full", &next).unwrap();
+        (1..=4).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+
+        assert!(sst.insert(key(5), value(5)).is_err());
+        assert!(sst.delete(key(1)).is_err());
+        // the failure doesn't break reads, and no later write gets into the broken segment
+        (1..=4).for_each(|i| assert_eq!(sst.get(&key(i)), Some(value(i))));
+        assert_eq!(sst.get(&key(5)), None);
+        assert!(sst.insert(key(6), value(6)).is_err());
+
+        drop(sst);
+        std::fs::remove_file(&next).unwrap();
+        let sst = SSTable::with_options("./test_tmp32", options());
+        (1..=4).for_each(|i| assert_eq!(sst.get(&key(i)), Some(value(i))));
+        assert_eq!(sst.get(&key(5)), None);
+    }
+
+    #[test]
+    fn test_sstable_wal_segments() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let options = || Options {
+            mem_max_entry: Some(3),
+            compaction_threshold: None,
+            wal_retention: WalRetention::Count(2),
+            ..Default::default()
+        };
+        let archive = "./test_tmp15/wal_archive";
+        let sst = SSTable::with_options("./test_tmp15", options());
+        assert!(sst.clear().is_ok());
+        let _ = std::fs::remove_dir_all(archive);
+        (1..=3).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        assert_eq!(wal_segments("./test_tmp15").len(), 1);
+
+        drop(sst);
+        // entries restored from a segment stay in it until they are flushed
+        let sst = SSTable::with_options("./test_tmp15", options());
+        assert_eq!(wal_segments("./test_tmp15").len(), 2);
+        sst.insert(key(4), value(4)).expect("success");
+        sst.wait_for_flushes().expect("success");
+        assert_eq!(wal_segments("./test_tmp15").len(), 1);
+        assert_eq!(wal_segments(archive).len(), 2);
+
+        // only the newest archived segments are kept
+        (5..=12).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        let archived = wal_segments(archive);
+        assert_eq!(archived.len(), 2);
+        assert!(archived[0].ends_with("wal_000004.log"));
+        assert!(archived[1].ends_with("wal_000005.log"));
+
+        drop(sst);
+        let sst = SSTable::with_options("./test_tmp15", options());
+        (1..=12).for_each(|i| assert_eq!(sst.get(&key(i)), Some(value(i))));
+    }
+
+    #[test]
+    fn test_sstable_wal_recovery_mode() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let dir = std::path::Path::new("./test_tmp16");
+        let sst = SSTable::new("./test_tmp16", 100);
+        assert!(sst.clear().is_ok());
+        (1..=5).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        let mut bytes = std::fs::read(wal_segments("./test_tmp16").pop().unwrap()).unwrap();
+        // each record takes 28 bytes. break the key of the 3rd record
+        bytes[28 * 2 + 18] ^= 0x01;
+        // and leave a torn record at the tail
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        let open = |wal_recovery_mode| {
+            wal_segments("./test_tmp16")
+                .iter()
+                .for_each(|path| std::fs::remove_file(path).unwrap());
+            std::fs::write(dir.join("wal_000100.log"), &bytes).unwrap();
+            SSTable::open(
+                "./test_tmp16",
+                Options {
+                    mem_max_entry: Some(100),
+                    wal_recovery_mode,
+                    ..Default::default()
+                },
+            )
+        };
+        assert!(open(WalRecoveryMode::AbsoluteConsistency).is_err());
+        assert!(open(WalRecoveryMode::TolerateCorruptedTailRecords).is_err());
+
+        let sst = open(WalRecoveryMode::PointInTime).unwrap();
+        let report = sst.wal_recovery_report();
+        assert_eq!(report.recovered_records, 2);
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].offset, 28 * 2);
+        assert_eq!(report.dropped[0].bytes, 28 * 3 + 3);
+        assert_eq!(sst.get(&key(2)), Some(value(2)));
+        assert_eq!(sst.get(&key(4)), None);
+
+        let sst = open(WalRecoveryMode::SkipAnyCorruptedRecords).unwrap();
+        let report = sst.wal_recovery_report();
+        assert_eq!(report.recovered_records, 4);
+        assert_eq!(report.dropped.len(), 2);
+        assert_eq!(sst.get(&key(3)), None);
+        assert_eq!(sst.get(&key(5)), Some(value(5)));
+
+        // the recovered entries are kept, and the broken segment is not replayed again
+        drop(sst);
+        let sst = SSTable::open(
+            "./test_tmp16",
+            Options {
+                mem_max_entry: Some(100),
+                wal_recovery_mode: WalRecoveryMode::AbsoluteConsistency,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(sst.wal_recovery_report().dropped, vec![]);
+        assert_eq!(sst.wal_recovery_report().recovered_records, 4);
+        assert_eq!(sst.get(&key(5)), Some(value(5)));
+    }
+
+    #[test]
+    fn test_sstable_wal_broken_length() {
+        let key = |i| format!("key-{:02}", i);
+        let sst = SSTable::new("./test_tmp31", 100);
+        assert!(sst.clear().is_ok());
+        (0..20).for_each(|i| {
+            sst.insert(key(i), i.to_string()).expect("success");
+        });
+        sst.sync().expect("success");
+        drop(sst);
+
+        // the length of the first record points past the end of the segment,
+        // which is not a torn record but a broken length
+        let segment = wal_segments("./test_tmp31").pop().unwrap();
+        let mut bytes = std::fs::read(&segment).unwrap();
+        bytes[0..4].copy_from_slice(&[0xff, 0xff, 0xff, 0x7f]);
+        std::fs::write(&segment, &bytes).unwrap();
+        let open = |wal_recovery_mode| {
+            SSTable::<String>::open(
+                "./test_tmp31",
+                Options {
+                    mem_max_entry: Some(100),
+                    wal_recovery_mode,
+                    ..Default::default()
+                },
+            )
+        };
+        let err = open(WalRecoveryMode::TolerateCorruptedTailRecords)
+            .err()
+            .expect("failure");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&segment).unwrap(), bytes);
+
+        let sst = open(WalRecoveryMode::PointInTime).unwrap();
+        let report = sst.wal_recovery_report();
+        assert_eq!(report.recovered_records, 0);
+        assert_eq!(report.dropped.len(), 1);
+        assert_ne!(report.dropped[0].reason, "torn record");
+    }
+
+    #[test]
+    fn test_sstable_wal_torn_record_holding_records() {
+        let sst = SSTable::<Vec<u8>, Vec<u8>>::new("./test_tmp34", 100);
+        assert!(sst.clear().is_ok());
+        sst.insert(b"a".to_vec(), b"1".to_vec()).expect("success");
+        let segment = wal_segments("./test_tmp34").pop().unwrap();
+        let record = std::fs::read(&segment).unwrap();
+
+        // the value of a record torn at the tail holds a whole valid record
+        let value = [&record[..], b"padding"].concat();
+        sst.insert(b"b".to_vec(), value).expect("success");
+        drop(sst);
+        let bytes = std::fs::read(&segment).unwrap();
+        std::fs::write(&segment, &bytes[..bytes.len() - 1]).unwrap();
+
+        let sst = SSTable::<Vec<u8>, Vec<u8>>::open(
+            "./test_tmp34",
+            Options {
+                mem_max_entry: Some(100),
+                wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let report = sst.wal_recovery_report();
+        assert_eq!(report.recovered_records, 1);
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].reason, "torn record");
+        assert_eq!(sst.get(&b"a".to_vec()), Some(b"1".to_vec()));
+        assert_eq!(sst.get(&b"b".to_vec()), None);
+    }
+
+    #[test]
+    fn test_sstable_bytes() {
+        // keys and values which are not valid UTF-8, and contain NUL
+        let key = |i: u8| vec![0xff, 0x00, i];
+        let value = |i: u8| vec![0x80, i, 0x00, 0xfe];
+        let sst = SSTable::<Vec<u8>, Vec<u8>>::new("./test_tmp17", 3);
+        assert!(sst.clear().is_ok());
+        (1..=10).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        sst.delete(key(2)).expect("success");
+        sst.delete(key(9)).expect("success");
+        assert!(sst.compact().is_ok());
+        assert_eq!(sst.get(&key(1)), Some(value(1)));
+        assert_eq!(sst.get(&key(2)), None);
+
+        drop(sst);
+        // restore WAL, and read from data files
+        let sst = SSTable::<Vec<u8>, Vec<u8>>::new("./test_tmp17", 3);
+        (1..=10).for_each(|i| {
+            let expected = if i == 2 || i == 9 {
+                None
+            } else {
+                Some(value(i))
+            };
+            assert_eq!(sst.get(&key(i)), expected);
+        });
+
+        // a value which is not UTF-8 can't be read as `String`
+        sst.insert(b"text".to_vec(), value(1)).expect("success");
+        drop(sst);
+        let sst = SSTable::<String, String>::new("./test_tmp17", 3);
+        assert!(sst.try_get(&"text".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_sstable_codec() {
+        // encoded integers are sorted in numerical order
+        assert!((-1i64).encode() < 0i64.encode());
+        assert!(i64::MIN.encode() < (-1i64).encode());
+        assert!(255u64.encode() < 256u64.encode());
+        assert_eq!(i64::decode(&(-42i64).encode()).unwrap(), -42);
+        assert!(u64::decode(&[0, 1]).is_err());
+
+        let key = |i: i64| i * 1000 - 50_000;
+        let sst = SSTable::<i64, [u8; 16]>::new("./test_tmp18", 10);
+        assert!(sst.clear().is_ok());
+        (0..100).for_each(|i| {
+            sst.insert(key(i), [i as u8; 16]).expect("success");
+        });
+        sst.delete(key(3)).expect("success");
+        assert!(sst.compact().is_ok());
+
+        drop(sst);
+        let sst = SSTable::<i64, [u8; 16]>::new("./test_tmp18", 10);
+        (0..100).for_each(|i| {
+            let expected = if i == 3 { None } else { Some([i as u8; 16]) };
+            assert_eq!(sst.get(&key(i)), expected);
+        });
+        assert_eq!(sst.get(&-1), None);
+    }
+
+    #[test]
+    fn test_sstable_range() {
+        use std::ops::Bound;
+        let key = |i| format!("key-{:03}", i);
+        let value = |i, round| format!("value-{}-{}", i, round);
+        let sst = SSTable::new("./test_tmp19", 10);
+        assert!(sst.clear().is_ok());
+        let mut expected = std::collections::BTreeMap::new();
+        // spread entries over several generations and the memtable,
+        // with newer values and deletions shadowing older ones
+        for round in 0..3 {
+            (0..100).step_by(round + 1).for_each(|i| {
+                sst.insert(key(i), value(i, round)).expect("success");
+                expected.insert(key(i), value(i, round));
+            });
+            (round * 7..100).step_by(11).for_each(|i| {
+                sst.delete(key(i)).expect("success");
+                expected.remove(&key(i));
+            });
+        }
+
+        let collect = |sst: &SSTable, range: (Bound<String>, Bound<String>)| {
+            sst.range(range)
+                .collect::<Result<Vec<_>, _>>()
+                .expect("success")
+        };
+        let bounds = vec![
+            (Bound::Unbounded, Bound::Unbounded),
+            (Bound::Included(key(10)), Bound::Excluded(key(50))),
+            (Bound::Excluded(key(11)), Bound::Included(key(77))),
+            (Bound::Included("key-0205".to_string()), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Included(key(0))),
+            (Bound::Included(key(60)), Bound::Included(key(20))),
+            (Bound::Excluded(key(30)), Bound::Excluded(key(30))),
+        ];
+        for range in bounds {
+            let expected: Vec<_> = expected
+                .iter()
+                .filter(|(k, _)| std::ops::RangeBounds::contains(&range, *k))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            assert_eq!(collect(&sst, range.clone()), expected, "{:?}", range);
+        }
+        assert_eq!(sst.range(key(40)..key(45)).count(), 4);
+
+        assert!(sst.compact().is_ok());
+        let all: Vec<_> = expected.into_iter().collect();
+        assert_eq!(collect(&sst, (Bound::Unbounded, Bound::Unbounded)), all);
+    }
+
+    #[test]
+    fn test_sstable_scan_prefix() {
+        let key = |user, item| format!("user/{}/item/{:02}", user, item);
+        let sst = SSTable::new("./test_tmp20", 8);
+        assert!(sst.clear().is_ok());
+        (0..12).for_each(|user| {
+            (0..10).for_each(|item| {
+                sst.insert(key(user, item), format!("{}", item))
+                    .expect("success");
+            });
+        });
+        sst.delete(key(4, 3)).expect("success");
+        sst.insert(key(4, 5), "new".to_string()).expect("success");
+
+        let items: Vec<_> = sst
+            .scan_prefix("user/4/")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("success");
+        assert_eq!(items.len(), 9);
+        assert!(items.iter().all(|(k, _)| k.starts_with("user/4/")));
+        assert!(!items.iter().any(|(k, _)| k == &key(4, 3)));
+        assert!(items.contains(&(key(4, 5), "new".to_string())));
+        // "user/1/" doesn't include "user/10/" nor "user/11/"
+        assert_eq!(sst.scan_prefix("user/1/").count(), 10);
+        assert_eq!(sst.scan_prefix("user/1").count(), 30);
+        assert_eq!(sst.scan_prefix("").count(), 119);
+        assert_eq!(sst.scan_prefix("user/99/").count(), 0);
+
+        // a prefix ending with 0xff has no upper bound of the same length
+        drop(sst);
+        let sst = SSTable::<Vec<u8>, Vec<u8>>::new("./test_tmp20", 8);
+        assert!(sst.clear().is_ok());
+        let keys = [
+            vec![0xfe, 0xff],
+            vec![0xff],
+            vec![0xff, 0x00],
+            vec![0xff, 0xff, 0x01],
+        ];
+        keys.iter().for_each(|k| {
+            sst.insert(k.clone(), vec![]).expect("success");
+        });
+        let scanned = |prefix: &[u8]| {
+            sst.scan_prefix(prefix)
+                .map(|entry| entry.unwrap().0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(scanned(&[0xff]), keys[1..].to_vec());
+        assert_eq!(scanned(&[0xff, 0xff]), keys[3..].to_vec());
+        assert_eq!(scanned(&[0xfe]), keys[..1].to_vec());
+    }
+
+    #[test]
+    fn test_sstable_cursor() {
+        let key = |i| format!("key-{:03}", i);
+        let sst = SSTable::new("./test_tmp21", 10);
+        assert!(sst.clear().is_ok());
+        let mut expected = std::collections::BTreeMap::new();
+        for round in 0..3 {
+            (0..100).step_by(round + 1).for_each(|i| {
+                let value = format!("{}-{}", i, round);
+                sst.insert(key(i), value.clone()).expect("success");
+                expected.insert(key(i), value);
+            });
+            (round * 5..100).step_by(7).for_each(|i| {
+                sst.delete(key(i)).expect("success");
+                expected.remove(&key(i));
+            });
+        }
+        let expected: Vec<_> = expected.into_iter().collect();
+
+        let mut cursor = sst.cursor();
+        // not positioned yet
+        cursor.next().unwrap();
+        assert_eq!(cursor.entry(), None);
+
+        let mut backward = vec![];
+        cursor.seek_to_last().unwrap();
+        while let Some((k, v)) = cursor.entry() {
+            backward.push((k.clone(), v.clone()));
+            cursor.prev().unwrap();
+        }
+        backward.reverse();
+        assert_eq!(backward, expected);
+
+        let mut forward = vec![];
+        cursor.seek_to_first().unwrap();
+        while let Some((k, v)) = cursor.entry() {
+            forward.push((k.clone(), v.clone()));
+            cursor.next().unwrap();
+        }
+        assert_eq!(forward, expected);
+
+        // seek to a deleted key, and then turn around
+        let deleted = (0..100).find(|i| !expected.iter().any(|(k, _)| k == &key(*i)));
+        let deleted = key(deleted.unwrap());
+        let pos = expected.partition_point(|(k, _)| k < &deleted);
+        cursor.seek(&deleted).unwrap();
+        assert_eq!(cursor.entry().map(|(k, _)| k), Some(&expected[pos].0));
+        cursor.prev().unwrap();
+        assert_eq!(cursor.entry().map(|(k, _)| k), Some(&expected[pos - 1].0));
+        cursor.next().unwrap();
+        cursor.next().unwrap();
+        assert_eq!(cursor.entry().map(|(k, _)| k), Some(&expected[pos + 1].0));
+
+        // "latest N" from the end
+        cursor.seek(&"key-999".to_string()).unwrap();
+        assert_eq!(cursor.entry(), None);
+        cursor.seek_to_last().unwrap();
+        let mut latest = vec![];
+        while let (Some((k, _)), true) = (cursor.entry(), latest.len() < 3) {
+            latest.push(k.clone());
+            cursor.prev().unwrap();
+        }
+        let n = expected.len();
+        assert_eq!(
+            latest,
+            vec![
+                expected[n - 1].0.clone(),
+                expected[n - 2].0.clone(),
+                expected[n - 3].0.clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sstable_snapshot() {
+        let key = |i| format!("key-{:03}", i);
+        let options = || Options {
+            mem_max_entry: Some(5),
+            compaction_threshold: Some(2),
+            ..Default::default()
+        };
+        let sst = SSTable::with_options("./test_tmp22", options());
+        assert!(sst.clear().is_ok());
+        (0..30).for_each(|i| {
+            sst.insert(key(i), format!("{}-old", i)).expect("success");
+        });
+        sst.delete(key(0)).expect("success");
+        let snapshot = sst.snapshot();
+        let expected: Vec<_> = (1..30).map(|i| (key(i), format!("{}-old", i))).collect();
+
+        // overwrite, delete and add keys across flushes and compactions
+        (0..30).step_by(2).for_each(|i| {
+            sst.insert(key(i), format!("{}-new", i)).expect("success");
+        });
+        (0..30).step_by(3).for_each(|i| {
+            sst.delete(key(i)).expect("success");
+        });
+        (30..40).for_each(|i| {
+            sst.insert(key(i), format!("{}-new", i)).expect("success");
+        });
+        sst.compact().expect("success");
+
+        assert_eq!(snapshot.get(&sst, &key(0)).unwrap(), None);
+        assert_eq!(
+            snapshot.get(&sst, &key(3)).unwrap(),
+            Some("3-old".to_string())
+        );
+        assert_eq!(
+            snapshot.get(&sst, &key(4)).unwrap(),
+            Some("4-old".to_string())
+        );
+        assert_eq!(snapshot.get(&sst, &key(35)).unwrap(), None);
+        assert_eq!(sst.get(&key(3)), None);
+        assert_eq!(sst.get(&key(4)), Some("4-new".to_string()));
+        let range: Vec<_> = snapshot
+            .range(&sst, ..)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(range, expected);
+        let mut cursor = snapshot.cursor(&sst).unwrap();
+        cursor.seek_to_last().unwrap();
+        assert_eq!(cursor.entry(), Some((&key(29), &"29-old".to_string())));
+        cursor.seek(&key(0)).unwrap();
+        assert_eq!(cursor.entry(), Some((&key(1), &"1-old".to_string())));
+        drop(cursor);
+
+        // a snapshot can't read another SSTable
+        let other = SSTable::<String>::new("./test_tmp22_other", 5);
+        assert!(snapshot.get(&other, &key(1)).is_err());
+
+        // old versions are dropped once no snapshot needs them, and sequence numbers survive reopening
+        let seq = snapshot.sequence();
+        drop(snapshot);
+        sst.compact().expect("success");
+        drop(sst);
+        let sst = SSTable::<String>::with_options("./test_tmp22", options());
+        assert!(sst.snapshot().sequence() > seq);
+        assert_eq!(sst.get(&key(3)), None);
+        assert_eq!(sst.get(&key(4)), Some("4-new".to_string()));
+        // every third key in 0..30 is deleted
+        assert_eq!(sst.range(..).count(), 40 - 10);
+    }
+
+    #[test]
+    fn test_sstable_range_excludes_versions_across_blocks() {
+        use std::ops::Bound;
+        let sst = SSTable::with_options(
+            "./test_tmp33",
+            Options {
+                mem_max_entry: Some(4),
+                compaction_threshold: None,
+                block_size: 1,
+                block_restart_interval: 1,
+                ..Default::default()
+            },
+        );
+        assert!(sst.clear().is_ok());
+        // the snapshots keep every version of "b" in its own block
+        sst.insert("b".to_string(), "b1".to_string())
+            .expect("success");
+        let _old = sst.snapshot();
+        sst.insert("b".to_string(), "b2".to_string())
+            .expect("success");
+        let _older = sst.snapshot();
+        sst.insert("b".to_string(), "b3".to_string())
+            .expect("success");
+        sst.insert("c".to_string(), "c1".to_string())
+            .expect("success");
+        sst.insert("d".to_string(), "d1".to_string())
+            .expect("success");
+        sst.wait_for_flushes().expect("success");
+
+        let range: Vec<_> = sst
+            .range((Bound::Excluded("b".to_string()), Bound::Unbounded))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            range,
+            vec![
+                ("c".to_string(), "c1".to_string()),
+                ("d".to_string(), "d1".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sstable_write_batch() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let sst = SSTable::with_options(
+            "./test_tmp23",
+            Options {
+                mem_max_entry: Some(3),
+                compaction_threshold: None,
+                ..Default::default()
+            },
+        );
+        assert!(sst.clear().is_ok());
+        sst.insert(key(0), value(0)).expect("success");
+        let mut batch = WriteBatch::new();
+        (1..=10).for_each(|i| batch.put(key(i), value(i)));
+        batch.delete(key(0));
+        batch.delete(key(2));
+        batch.put(key(2), value(200));
+        sst.write(batch).expect("success");
+        sst.wait_for_flushes().expect("success");
+        // flushed once after the whole batch
+        assert_eq!(count_data_files("./test_tmp23"), 1);
+        assert_eq!(sst.get(&key(0)), None);
+        assert_eq!(sst.get(&key(2)), Some(value(200)));
+        assert_eq!(sst.get(&key(10)), Some(value(10)));
+
+        drop(sst);
+        // a batch torn by a crash is dropped as a whole
+        let sst = SSTable::new("./test_tmp23", 100);
+        sst.insert(key(11), value(11)).expect("success");
+        let mut batch = WriteBatch::new();
+        batch.put(key(12), value(12));
+        batch.delete(key(1));
+        sst.write(batch).expect("success");
+        let segment = wal_segments("./test_tmp23").pop().unwrap();
+        let bytes = std::fs::read(&segment).unwrap();
+        std::fs::write(&segment, &bytes[..bytes.len() - 1]).unwrap();
+        drop(sst);
+        let sst = SSTable::new("./test_tmp23", 100);
+        assert_eq!(sst.wal_recovery_report().recovered_records, 1);
+        assert_eq!(sst.get(&key(11)), Some(value(11)));
+        assert_eq!(sst.get(&key(12)), None);
+        assert_eq!(sst.get(&key(1)), Some(value(1)));
+    }
+
+    #[test]
+    fn test_sstable_transaction() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let is_conflict = |err: &std::io::Error| {
+            err.get_ref()
+                .is_some_and(|err| err.downcast_ref::<TransactionConflict>().is_some())
+        };
+        let sst = SSTable::new("./test_tmp24", 3);
+        assert!(sst.clear().is_ok());
+        (1..=5).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+
+        // read-your-own-writes
+        let mut txn = sst.begin_transaction();
+        assert_eq!(txn.get(&sst, &key(1)).unwrap(), Some(value(1)));
+        txn.insert(key(1), value(100));
+        txn.delete(key(2));
+        assert_eq!(txn.get(&sst, &key(1)).unwrap(), Some(value(100)));
+        assert_eq!(txn.get(&sst, &key(2)).unwrap(), None);
+        assert_eq!(sst.get(&key(1)), Some(value(1)));
+        txn.commit(&sst).expect("success");
+        assert_eq!(sst.get(&key(1)), Some(value(100)));
+        assert_eq!(sst.get(&key(2)), None);
+
+        // a key read was modified, even after being flushed to disk
+        let mut txn = sst.begin_transaction();
+        assert_eq!(txn.get(&sst, &key(3)).unwrap(), Some(value(3)));
+        txn.insert(key(6), value(6));
+        sst.insert(key(3), value(300)).expect("success");
+        (7..=10).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        assert_eq!(txn.get(&sst, &key(3)).unwrap(), Some(value(3)));
+        let err = txn.commit(&sst).unwrap_err();
+        assert!(is_conflict(&err));
+        assert_eq!(sst.get(&key(6)), None);
+
+        // a key written was deleted by someone else
+        let mut txn = sst.begin_transaction();
+        txn.insert(key(4), value(400));
+        sst.delete(key(4)).expect("success");
+        assert!(is_conflict(&txn.commit(&sst).unwrap_err()));
+        assert_eq!(sst.get(&key(4)), None);
+
+        // keys untouched by others don't conflict
+        let mut txn = sst.begin_transaction();
+        assert_eq!(txn.get(&sst, &key(5)).unwrap(), Some(value(5)));
+        txn.insert(key(11), value(11));
+        sst.insert(key(12), value(12)).expect("success");
+        txn.commit(&sst).expect("success");
+        assert_eq!(sst.get(&key(11)), Some(value(11)));
+    }
+
+    #[test]
+    fn test_sstable_concurrent_access() {
+        use std::{sync::Arc, thread};
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SSTable>();
+
+        let key = |i| format!("key-{:04}", i);
+        let value = |i| format!("value-{}", i);
+        let sst = Arc::new(SSTable::new("./test_tmp25", 10));
+        assert!(sst.clear().is_ok());
+
+        let writer = {
+            let sst = sst.clone();
+            thread::spawn(move || {
+                (0..500).for_each(|i| {
+                    sst.insert(key(i), value(i)).expect("success");
+                    if i % 100 == 99 {
+                        sst.compact().expect("success");
+                    }
+                });
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let sst = sst.clone();
+                thread::spawn(move || {
+                    let mut last_count = 0;
+                    while last_count < 500 {
+                        // keys are inserted in order, so a range sees a prefix of them
+                        let entries: Vec<_> = sst.range(..).map(Result::unwrap).collect();
+                        let expected: Vec<_> =
+                            (0..entries.len()).map(|i| (key(i), value(i))).collect();
+                        assert_eq!(entries, expected);
+                        assert!(entries.len() >= last_count);
+                        last_count = entries.len();
+                        if last_count > 0 {
+                            assert_eq!(sst.get(&key(last_count - 1)), Some(value(last_count - 1)));
+                        }
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        readers
+            .into_iter()
+            .for_each(|reader| reader.join().unwrap());
+
+        assert_eq!(sst.range(..).count(), 500);
+        assert_eq!(sst.get(&key(499)), Some(value(499)));
+    }
+
+    #[test]
+    fn test_sstable_background_flush() {
+        let key = |i| format!("key-{:02}", i);
+        let value = |i| format!("value-{}", i);
+        let options = || Options {
+            mem_max_entry: Some(3),
+            max_immutable_memtables: 8,
+            compaction_threshold: None,
+            ..Default::default()
+        };
+        let sst = SSTable::with_options("./test_tmp26", options());
+        assert!(sst.clear().is_ok());
+        (1..=40).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+            // entries are readable whether they are in the memtable, queued, or flushed
+            assert_eq!(sst.get(&key(i)), Some(value(i)));
+            assert_eq!(sst.range(..).count(), i);
+        });
+        // memtables left in the queue are flushed on drop
+        drop(sst);
+        assert_eq!(count_data_files("./test_tmp26"), 10);
+        assert_eq!(wal_segments("./test_tmp26").len(), 1);
+
+        let sst = SSTable::with_options("./test_tmp26", options());
+        (1..=40).for_each(|i| assert_eq!(sst.get(&key(i)), Some(value(i))));
+        assert!(sst.clear().is_ok());
+        sst.wait_for_flushes().expect("success");
+        assert_eq!(count_data_files("./test_tmp26"), 0);
+    }
+
+    #[test]
+    fn test_sstable_background_compaction() {
+        let key = |i| format!("key-{:04}", i);
+        let value = |i| format!("value-{}", i);
+        let options = |mem_max_entry| Options {
+            mem_max_entry: Some(mem_max_entry),
+            compaction_threshold: Some(2),
+            ..Default::default()
+        };
+        let tmp_files = || {
+            std::fs::read_dir("./test_tmp27")
+                .unwrap()
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    name.to_string_lossy().starts_with("tmp_data")
+                })
+                .count()
+        };
+        let sst = SSTable::with_options("./test_tmp27", options(3));
+        assert!(sst.clear().is_ok());
+
+        // no compaction runs while paused, but flushes go on
+        sst.pause_background_work();
+        (1..=20).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        sst.wait_for_compactions().expect("success");
+        assert_eq!(count_data_files("./test_tmp27"), 5);
+        assert!(sst.compact().is_err());
+
+        // compactions which came due while paused run on resume
+        sst.resume_background_work();
+        sst.wait_for_compactions().expect("success");
+        assert_eq!(count_data_files("./test_tmp27"), 1);
+        sst.close().expect("success");
+
+        // closing abandons a running compaction without leaving a half-written file
+        let sst = SSTable::with_options("./test_tmp27", options(100));
+        (21..=3000).for_each(|i| {
+            sst.insert(key(i), value(i)).expect("success");
+        });
+        sst.close().expect("success");
+        assert_eq!(tmp_files(), 0);
+        let sst = SSTable::with_options("./test_tmp27", options(100));
+        (1..=3000).for_each(|i| assert_eq!(sst.get(&key(i)), Some(value(i))));
+    }
+
+    #[test]
+    fn test_sstable_write_buffer_size() {
+        let key = |i| format!("key-{:03}", i);
+        let options = || Options {
+            write_buffer_size: 1024,
+            compaction_threshold: None,
+            ..Default::default()
+        };
+        let sst = SSTable::with_options("./test_tmp28", options());
+        assert!(sst.clear().is_ok());
+        // a version of 7 bytes of key and 200 bytes of value takes 215 bytes
+        (1..=10).for_each(|i| {
+            sst.insert(key(i), "x".repeat(200)).expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        assert_eq!(count_data_files("./test_tmp28"), 2);
+
+        // an overwritten version which no snapshot can see doesn't count
+        (1..=100).for_each(|_| {
+            sst.insert(key(0), "x".repeat(200)).expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        assert_eq!(count_data_files("./test_tmp28"), 2);
+
+        // small entries fill the memtable as well, and so do tombstones
+        (11..=40).for_each(|i| {
+            sst.insert(key(i), "y".to_string()).expect("success");
+        });
+        (101..=130).for_each(|i| {
+            sst.delete(key(i)).expect("success");
+        });
+        sst.insert(key(41), "y".to_string()).expect("success");
+        sst.wait_for_flushes().expect("success");
+        assert_eq!(count_data_files("./test_tmp28"), 3);
+        assert_eq!(sst.get(&key(0)), Some("x".repeat(200)));
+        assert_eq!(sst.get(&key(11)), Some("y".to_string()));
+
+        // the count limit is still a trigger if given
+        drop(sst);
+        let sst = SSTable::<String, String>::with_options(
+            "./test_tmp28",
+            Options {
+                mem_max_entry: Some(3),
+                ..options()
+            },
+        );
+        (42..=45).for_each(|i| {
+            sst.insert(key(i), "z".to_string()).expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        assert_eq!(count_data_files("./test_tmp28"), 4);
+    }
+
+    #[test]
+    fn test_sstable_delete_flush() {
+        let key = |i| format!("key-{:03}", i);
+        let options = || Options {
+            mem_max_entry: Some(10),
+            compaction_threshold: None,
+            ..Default::default()
+        };
+        let sst = SSTable::with_options("./test_tmp29", options());
+        assert!(sst.clear().is_ok());
+        (0..=10).for_each(|i| {
+            sst.insert(key(i), i.to_string()).expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        assert_eq!(count_data_files("./test_tmp29"), 1);
+
+        // deletes alone fill the memtable, and their tombstones are flushed
+        (0..=10).for_each(|i| {
+            sst.delete(key(i)).expect("success");
+        });
+        sst.wait_for_flushes().expect("success");
+        assert_eq!(count_data_files("./test_tmp29"), 2);
+        (0..=10).for_each(|i| assert_eq!(sst.get(&key(i)), None));
+
+        drop(sst);
+        let sst: SSTable<String, String> = SSTable::with_options("./test_tmp29", options());
+        assert_eq!(count_data_files("./test_tmp29"), 2);
+        (0..=10).for_each(|i| assert_eq!(sst.get(&key(i)), None));
     }
 }
@@ -0,0 +1,375 @@
+//! Flushes, compactions and timed WAL syncs run by background threads of `SSTable`.
+
+use super::{memtable::WriteAheadLog, Tables};
+use log;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// State shared between `SSTable` and its background threads.
+pub(crate) struct Shared {
+    pub tables: RwLock<Tables>,
+    // serializes writes, which append to WAL without holding `tables`
+    pub writer: Mutex<WriteAheadLog>,
+    state: Mutex<State>,
+    // notified whenever `state` changes
+    changed: Condvar,
+    // tells a running compaction to give up
+    abandon: AtomicBool,
+}
+
+#[derive(Default)]
+struct State {
+    flush_requested: bool,
+    // bumped whenever memtables leave the flush queue
+    flush_epoch: u64,
+    compaction_requested: bool,
+    // tickets of full compactions requested by `SSTable::compact`, and the last one done
+    full_requested: u64,
+    full_done: u64,
+    full_error: Option<io::Error>,
+    compacting: bool,
+    // no compaction starts while this is positive
+    paused: usize,
+    shutdown: bool,
+    // the error which stopped background work
+    error: Option<io::Error>,
+}
+
+// `io::Error` can't be cloned, so the error is reported as a new one of the same kind
+fn report(err: &io::Error, context: &str) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", context, err))
+}
+
+impl Shared {
+    pub fn new(tables: Tables, wal: WriteAheadLog) -> Shared {
+        Shared {
+            tables: RwLock::new(tables),
+            writer: Mutex::new(wal),
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+            abandon: AtomicBool::new(false),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        f(&mut self.state());
+        self.changed.notify_all();
+    }
+
+    /// Fails once background work has failed, after which no memtable is flushed.
+    pub fn check_error(&self) -> io::Result<()> {
+        match &self.state().error {
+            Some(err) => Err(report(err, "background work failed")),
+            None => Ok(()),
+        }
+    }
+
+    /// Tells the flush thread that a memtable was queued.
+    pub fn request_flush(&self) {
+        self.update(|state| state.flush_requested = true);
+    }
+
+    /// Wakes up writers waiting for the flush queue to shrink.
+    pub fn notify_flushed(&self) {
+        self.update(|state| state.flush_epoch += 1);
+    }
+
+    /// Blocks while more than `max_queued` memtables are waiting to be flushed.
+    pub fn wait_for_flushes(&self, max_queued: usize) -> io::Result<()> {
+        loop {
+            // the epoch is read first, not to miss a flush done right after the check
+            let epoch = self.state().flush_epoch;
+            if self.tables.read().unwrap().disktable.queued_flushes() <= max_queued {
+                return Ok(());
+            }
+            let mut state = self.state();
+            while state.flush_epoch == epoch && state.error.is_none() {
+                state = self.changed.wait(state).unwrap();
+            }
+            drop(state);
+            self.check_error()?;
+        }
+    }
+
+    /// Has the compaction thread merge every generation, and waits for it.
+    pub fn compact_all(&self) -> io::Result<()> {
+        let mut state = self.state();
+        if state.paused > 0 {
+            return Err(io::Error::other("background work is paused"));
+        }
+        state.full_requested += 1;
+        let ticket = state.full_requested;
+        self.changed.notify_all();
+        while state.full_done < ticket {
+            if let Some(err) = &state.error {
+                return Err(report(err, "background work failed"));
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+        match &state.full_error {
+            Some(err) => Err(report(err, "compaction failed")),
+            None => Ok(()),
+        }
+    }
+
+    /// Blocks until no compaction is running or due, unless background work is paused.
+    pub fn wait_for_compactions(&self) -> io::Result<()> {
+        let mut state = self.state();
+        while state.paused == 0
+            && state.error.is_none()
+            && (state.compacting
+                || state.compaction_requested
+                || state.full_requested > state.full_done)
+        {
+            state = self.changed.wait(state).unwrap();
+        }
+        drop(state);
+        self.check_error()
+    }
+
+    /// Stops starting compactions, and waits for the running one to finish,
+    /// or to be abandoned if `abandon`.
+    pub fn pause(&self, abandon: bool) {
+        let mut state = self.state();
+        state.paused += 1;
+        if abandon {
+            self.abandon.store(true, Ordering::Relaxed);
+        }
+        self.changed.notify_all();
+        while state.compacting {
+            state = self.changed.wait(state).unwrap();
+        }
+        self.abandon.store(false, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.update(|state| state.paused = state.paused.saturating_sub(1));
+    }
+
+    // flushes memtables in the queue from oldest to newest until it gets empty
+    fn flush_queued(&self) {
+        loop {
+            if self.state().error.is_some() {
+                return;
+            }
+            let mut job = match self.tables.read().unwrap().disktable.flush_job() {
+                Some(job) => job,
+                None => return,
+            };
+            // the data file is written without the lock, so that reads and writes go on meanwhile
+            let result = job.run().and_then(|_| {
+                let mut tables = self.tables.write().unwrap();
+                tables.disktable.install_flush(job)?;
+                // a new generation may make compaction due. it is requested under the lock,
+                // so that whoever sees the flush done sees the request as well.
+                self.update(|state| state.compaction_requested = true);
+                Ok(())
+            });
+            self.update(|state| {
+                if let Err(err) = result {
+                    log::error!("background flush failed: {:?}", err);
+                    state.error = Some(err);
+                }
+                state.flush_epoch += 1;
+            });
+        }
+    }
+
+    // runs compaction jobs one by one while compaction is due,
+    // or just one covering every generation if `full`
+    fn compact(&self, full: bool) -> io::Result<()> {
+        loop {
+            {
+                let mut state = self.state();
+                if state.paused > 0 || state.shutdown {
+                    if full {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Interrupted,
+                            "compaction was cancelled",
+                        ));
+                    }
+                    // to be continued on resume
+                    state.compaction_requested = true;
+                    return Ok(());
+                }
+            }
+            let job = self
+                .tables
+                .write()
+                .unwrap()
+                .disktable
+                .compaction_job(full)
+                .map_err(|err| self.fail(err))?;
+            let mut job = match job {
+                Some(job) => job,
+                None => return Ok(()),
+            };
+            // inputs are read and outputs are written without the lock
+            job.run(&self.abandon)?;
+            self.tables
+                .write()
+                .unwrap()
+                .disktable
+                .install_compaction(job)
+                .map_err(|err| self.fail(err))?;
+            if full {
+                return Ok(());
+            }
+        }
+    }
+
+    // syncs WAL once per `interval`, so that no write stays unsynced much longer than that
+    // even if no write follows it
+    fn run_wal_sync_thread(&self, interval: Duration) {
+        loop {
+            let (state, _) = self
+                .changed
+                .wait_timeout_while(self.state(), interval, |state| !state.shutdown)
+                .unwrap();
+            if state.shutdown {
+                return;
+            }
+            drop(state);
+            if let Err(err) = self.writer.lock().unwrap().sync_pending() {
+                // the next write fails as WAL refuses writes after a failed sync
+                log::error!("background WAL sync failed: {:?}", err);
+            }
+        }
+    }
+
+    // stops background work on an error while changing generations, as MANIFEST may be left
+    // with a partial edit. other errors of a full compaction are only reported to its caller.
+    fn fail(&self, err: io::Error) -> io::Error {
+        log::error!("background compaction failed: {:?}", err);
+        let reported = report(&err, "failed to change generations");
+        self.update(|state| state.error = Some(err));
+        reported
+    }
+
+    fn run_flush_thread(&self) {
+        loop {
+            let shutdown = {
+                let mut state = self.state();
+                while !state.flush_requested && !state.shutdown {
+                    state = self.changed.wait(state).unwrap();
+                }
+                state.flush_requested = false;
+                state.shutdown
+            };
+            // what is left in the queue is flushed on shutdown as well
+            self.flush_queued();
+            if shutdown {
+                return;
+            }
+        }
+    }
+
+    fn run_compaction_thread(&self) {
+        loop {
+            let full_ticket = {
+                let mut state = self.state();
+                loop {
+                    if state.shutdown {
+                        return;
+                    }
+                    let requested =
+                        state.compaction_requested || state.full_requested > state.full_done;
+                    if state.paused == 0 && state.error.is_none() && requested {
+                        break;
+                    }
+                    state = self.changed.wait(state).unwrap();
+                }
+                state.compaction_requested = false;
+                state.compacting = true;
+                (state.full_requested > state.full_done).then_some(state.full_requested)
+            };
+            let result = self.compact(full_ticket.is_some());
+            self.update(|state| {
+                state.compacting = false;
+                match full_ticket {
+                    Some(ticket) => {
+                        state.full_done = ticket;
+                        state.full_error = result.err();
+                    }
+                    None => match result {
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                            // abandoned, and tried again later unless shutting down
+                            state.compaction_requested = true;
+                        }
+                        Err(err) => {
+                            log::error!("background compaction failed: {:?}", err);
+                            state.error = Some(err);
+                        }
+                        Ok(()) => {}
+                    },
+                }
+            });
+        }
+    }
+}
+
+/// The threads flushing, compacting and syncing WAL in the background.
+/// Shutting them down waits for memtables in the flush queue to be flushed,
+/// and abandons a running compaction.
+pub(crate) struct Workers {
+    shared: Arc<Shared>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Workers {
+    /// WAL is synced by a thread once per `sync_interval` if any.
+    pub fn spawn(shared: Arc<Shared>, sync_interval: Option<Duration>) -> io::Result<Workers> {
+        let mut workers = Workers {
+            shared,
+            handles: vec![],
+        };
+        workers.spawn_thread("sstable-flush", Shared::run_flush_thread)?;
+        workers.spawn_thread("sstable-compaction", Shared::run_compaction_thread)?;
+        if let Some(interval) = sync_interval {
+            workers.spawn_thread("sstable-wal-sync", move |shared| {
+                shared.run_wal_sync_thread(interval)
+            })?;
+        }
+        Ok(workers)
+    }
+
+    fn spawn_thread(
+        &mut self,
+        name: &str,
+        run: impl FnOnce(&Shared) + Send + 'static,
+    ) -> io::Result<()> {
+        let shared = self.shared.clone();
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || run(&shared))?;
+        self.handles.push(handle);
+        Ok(())
+    }
+
+    pub fn shutdown(&mut self) {
+        self.shared.abandon.store(true, Ordering::Relaxed);
+        self.shared.update(|state| state.shutdown = true);
+        for handle in self.handles.drain(..) {
+            if handle.join().is_err() {
+                log::error!("background thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
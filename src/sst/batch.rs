@@ -0,0 +1,60 @@
+//! Writes of multiple keys applied all or nothing.
+
+use super::codec::Codec;
+use std::marker::PhantomData;
+
+/// Puts and deletes applied to `SSTable` atomically by `SSTable::write`.
+/// They are written into WAL as one record, so either all or none of them survive a crash.
+/// A later op on the same key in a batch wins over an earlier one.
+pub struct WriteBatch<K = String, V = String> {
+    // encoded keys and values, where a delete has no value
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    codec: PhantomData<(K, V)>,
+}
+
+impl<K: Codec, V: Codec> WriteBatch<K, V> {
+    pub fn new() -> WriteBatch<K, V> {
+        WriteBatch {
+            ops: vec![],
+            codec: PhantomData,
+        }
+    }
+
+    pub fn put(&mut self, key: K, value: V) {
+        self.ops.push((key.encode(), Some(value.encode())));
+    }
+
+    pub fn delete(&mut self, key: K) {
+        self.ops.push((key.encode(), None));
+    }
+
+    /// Number of ops in the batch.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+
+    pub(crate) fn from_ops(ops: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> WriteBatch<K, V> {
+        WriteBatch {
+            ops,
+            codec: PhantomData,
+        }
+    }
+
+    pub(crate) fn into_ops(self) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        self.ops
+    }
+}
+
+impl<K: Codec, V: Codec> Default for WriteBatch<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
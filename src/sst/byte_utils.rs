@@ -0,0 +1,36 @@
+use std::convert::TryInto;
+
+pub(crate) struct ByteUtils;
+impl ByteUtils {
+    pub fn as_usize(array: &[u8]) -> usize {
+        u32::from_le_bytes(array.try_into().unwrap()) as usize
+    }
+    pub fn as_u64(array: &[u8]) -> u64 {
+        u64::from_le_bytes(array.try_into().unwrap())
+    }
+    pub fn from_usize(n: usize) -> [u8; 4] {
+        (n as u32).to_le_bytes()
+    }
+    pub fn from_u64(n: u64) -> [u8; 8] {
+        n.to_le_bytes()
+    }
+    /// Appends `n` as LEB128 variable length integer.
+    pub fn put_varint(buf: &mut Vec<u8>, mut n: u64) {
+        while n >= 0x80 {
+            buf.push((n as u8) | 0x80);
+            n >>= 7;
+        }
+        buf.push(n as u8);
+    }
+    /// Reads a variable length integer, and returns it with the number of bytes read.
+    pub fn get_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+        let mut n = 0u64;
+        for (i, byte) in bytes.iter().enumerate().take(10) {
+            n |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Some((n, i + 1));
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,69 @@
+//! Encoding of keys and values into bytes.
+
+use std::{convert::TryInto, io};
+
+/// A type which can be stored in `SSTable` as a key or a value.
+/// Keys are sorted by their encoded bytes, so the encoding of a key type
+/// defines the order of keys on disk.
+pub trait Codec: Sized {
+    fn encode(&self) -> Vec<u8>;
+    fn decode(bytes: &[u8]) -> io::Result<Self>;
+}
+
+fn invalid_length(expected: usize, bytes: &[u8]) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("expected {} bytes, but got {}", expected, bytes.len()),
+    )
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// UTF-8, which is sorted in the same order as `String`.
+impl Codec for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        String::from_utf8(bytes.to_vec())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// As is, e.g. for UUIDs.
+impl<const N: usize> Codec for [u8; N] {
+    fn encode(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        bytes.try_into().map_err(|_| invalid_length(N, bytes))
+    }
+}
+
+// big-endian, so that bytes are sorted in numerical order.
+// the sign bit of a signed integer is flipped to put negative numbers first.
+macro_rules! impl_integer_codec {
+    ($($int:ty => $sign:expr),*) => {
+        $(
+            impl Codec for $int {
+                fn encode(&self) -> Vec<u8> {
+                    (self ^ $sign).to_be_bytes().to_vec()
+                }
+                fn decode(bytes: &[u8]) -> io::Result<Self> {
+                    let bytes = bytes
+                        .try_into()
+                        .map_err(|_| invalid_length(std::mem::size_of::<$int>(), bytes))?;
+                    Ok(<$int>::from_be_bytes(bytes) ^ $sign)
+                }
+            }
+        )*
+    };
+}
+impl_integer_codec!(u32 => 0, u64 => 0, i32 => i32::MIN, i64 => i64::MIN);
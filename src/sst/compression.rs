@@ -0,0 +1,81 @@
+//! Compression of data blocks.
+
+use std::{fmt, io, sync::Arc};
+
+/// A codec to compress data blocks.
+/// Its `id` is persisted in each data file, so that the file can be read
+/// with the same codec even after `Options::compression` is changed.
+pub trait BlockCodec: Send + Sync {
+    /// identifier persisted in data files.
+    /// ids below `Compression::CUSTOM_ID_MIN` are reserved for built-in codecs.
+    fn id(&self) -> u8;
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+    fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+#[derive(Clone)]
+pub enum Compression {
+    /// blocks are stored as is
+    None,
+    /// blocks are compressed by Snappy
+    Snappy,
+    /// blocks are compressed by the given codec
+    Custom(Arc<dyn BlockCodec>),
+}
+
+impl Compression {
+    const NONE_ID: u8 = 0;
+    const SNAPPY_ID: u8 = 1;
+    pub const CUSTOM_ID_MIN: u8 = 16;
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::None => Self::NONE_ID,
+            Compression::Snappy => Self::SNAPPY_ID,
+            Compression::Custom(codec) => codec.id(),
+        }
+    }
+
+    /// Finds the codec of `id` among built-in ones and this one.
+    pub(crate) fn resolve(&self, id: u8) -> io::Result<Compression> {
+        match id {
+            Self::NONE_ID => Ok(Compression::None),
+            Self::SNAPPY_ID => Ok(Compression::Snappy),
+            _ if id == self.id() && id >= Self::CUSTOM_ID_MIN => Ok(self.clone()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown compression codec: {}", id),
+            )),
+        }
+    }
+
+    pub(crate) fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Snappy => snap::raw::Encoder::new()
+                .compress_vec(data)
+                .map_err(io::Error::other),
+            Compression::Custom(codec) => codec.compress(data),
+        }
+    }
+
+    pub(crate) fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Compression::Custom(codec) => codec.decompress(data),
+        }
+    }
+}
+
+impl fmt::Debug for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "None"),
+            Compression::Snappy => write!(f, "Snappy"),
+            Compression::Custom(codec) => write!(f, "Custom({})", codec.id()),
+        }
+    }
+}
@@ -1,140 +1,617 @@
-mod byte_utils;
+mod block;
+mod bloom_filter;
+mod compaction;
 mod data_file;
-mod index_file;
+mod levels;
+mod manifest;
 
-use super::memtable::MemtableEntries;
+use super::{
+    iter::{EntryIter, RawCursor},
+    memtable::{ImmutableMemtable, MemtableEntries},
+    snapshot::{SeqNo, SnapshotList},
+};
+pub use bloom_filter::FilterStats;
+pub(crate) use compaction::MergingIter;
+pub(crate) use data_file::DataEntry;
 use log;
-use std::{collections::BTreeMap, io};
+use std::{
+    io,
+    ops::Bound,
+    sync::{atomic::AtomicBool, atomic::AtomicI32, Arc},
+};
 
-pub(crate) trait Disktable {
-    fn find(&self, key: &str) -> Option<String>;
-    fn flush(&mut self, memtable_entries: MemtableEntries<String, String>)
-        -> Result<(), io::Error>;
+pub(crate) trait Disktable: Send + Sync {
+    /// What is needed to look up `key`, which runs without borrowing the disktable.
+    fn lookup(&self, key: &[u8]) -> KeyLookup;
+    /// Queues `memtable` to be flushed. Its entries are read from the queue until flushed.
+    fn schedule_flush(&mut self, memtable: QueuedMemtable);
+    /// A job to write the oldest memtable in the queue, which runs without borrowing the disktable.
+    fn flush_job(&self) -> Option<FlushJob>;
+    /// Adds the generation written by `job`, and removes its memtable from the queue.
+    fn install_flush(&mut self, job: FlushJob) -> Result<(), io::Error>;
+    /// The number of memtables in the queue.
+    fn queued_flushes(&self) -> usize;
+    /// A job to merge generations, which runs without borrowing the disktable.
+    /// `full` picks every generation as `SSTable::compact` does. Otherwise, generations are
+    /// picked by the strategy only when compaction is due, and `None` is returned if not.
+    fn compaction_job(&mut self, full: bool) -> Result<Option<CompactionJob>, io::Error>;
+    /// Swaps the inputs of `job` with its outputs.
+    fn install_compaction(&mut self, job: CompactionJob) -> Result<(), io::Error>;
+    /// Iterates over every version, including tombstones, from `start` in key order,
+    /// and then from newest to oldest.
+    /// Entries after `end` may be yielded as well, so the caller should stop at `end`.
+    /// The iterator shares what it reads, so that it is not affected by later flushes and compactions.
+    fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> EntryIter;
+    /// Raw cursors over memtables in the queue and every generation,
+    /// from newest to oldest. They share what they read as `range` does.
+    fn cursors(&self) -> Vec<Box<dyn RawCursor + Send>>;
     fn clear(&mut self) -> Result<(), io::Error>;
+    fn filter_stats(&self) -> FilterStats;
+    /// The latest sequence number persisted in data files.
+    fn last_sequence(&self) -> SeqNo;
 }
 type DataGen = i32; // data generation
+type QueuedMemtable = ImmutableMemtable<Vec<u8>, Vec<u8>>;
 type Offset = u64;
 
+/// An error for data on disk which is broken.
+pub(crate) fn corrupted(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Memtables in the queue and generations which may contain a key, returned by `Disktable::lookup`.
+/// They are shared, so that data files are read without the lock.
+pub(crate) struct KeyLookup {
+    // from newest to oldest
+    memtables: Vec<Arc<MemtableEntries<Vec<u8>, Vec<u8>>>>,
+    data_files: Vec<Arc<data_file::DataFile>>,
+    filter_counter: Arc<bloom_filter::FilterCounter>,
+}
+
+impl KeyLookup {
+    /// The newest version of `key`, including a tombstone, which is not newer than `seq`.
+    /// A tombstone in a newer generation shadows values in older generations.
+    pub fn find(&self, key: &[u8], seq: SeqNo) -> io::Result<Option<DataEntry>> {
+        let key_vec = key.to_vec();
+        for memtable in &self.memtables {
+            if let Some((key, seq, value)) = memtable.find(&key_vec, seq) {
+                return Ok(Some(DataEntry::new(key.clone(), seq, value.cloned())));
+            }
+        }
+        for data_file in &self.data_files {
+            if let Some(entry) = self.find_in_file(data_file, key, seq)? {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    fn find_in_file(
+        &self,
+        data_file: &Arc<data_file::DataFile>,
+        key: &[u8],
+        seq: SeqNo,
+    ) -> io::Result<Option<DataEntry>> {
+        let filter = data_file.filter();
+        if let Some(filter) = filter {
+            let may_contain = filter.may_contain(key);
+            self.filter_counter.checked(may_contain);
+            if !may_contain {
+                return Ok(None);
+            }
+        }
+        match data_file.find(key, seq)? {
+            data_file::Lookup::Found(entry) => Ok(Some(entry)),
+            data_file::Lookup::Invisible => Ok(None),
+            data_file::Lookup::Absent => {
+                // only a key missing from the file makes the filter wrong
+                if filter.is_some() {
+                    self.filter_counter.false_positive();
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Writing a queued memtable into a new generation, returned by `Disktable::flush_job`.
+pub(crate) struct FlushJob {
+    dir_name: String,
+    data_gen: DataGen,
+    entries: Arc<MemtableEntries<Vec<u8>, Vec<u8>>>,
+    oldest_snapshot: SeqNo,
+    options: data_file::DataFileOptions,
+    written: Option<levels::FileMeta>,
+}
+
+impl FlushJob {
+    pub fn run(&mut self) -> io::Result<()> {
+        // versions shadowed since a snapshot was released are dropped
+        let entries = compaction::retain_visible(
+            self.entries
+                .iter()
+                .map(|(key, seq, value)| Ok(DataEntry::new(key.clone(), seq, value.cloned()))),
+            self.oldest_snapshot,
+            false,
+        );
+        let meta =
+            compaction::write_generation(&self.dir_name, self.data_gen, 0, entries, &self.options)?;
+        self.written = Some(meta);
+        Ok(())
+    }
+}
+
+/// Merging generations into new ones, returned by `Disktable::compaction_job`.
+pub(crate) struct CompactionJob {
+    compaction: compaction::Compaction,
+    output_level: usize,
+    target_file_size: u64,
+    // outputs in level 0 take over the place of inputs, which is ordered by this
+    newest_gen: Option<DataGen>,
+    // shared with the disktable, which numbers flushed generations
+    data_gen: Arc<AtomicI32>,
+    outputs: Vec<levels::FileMeta>,
+}
+
+impl CompactionJob {
+    /// Writes the outputs. Once `abandon` is set, it fails with `ErrorKind::Interrupted`
+    /// leaving no output on disk.
+    pub fn run(&mut self, abandon: &AtomicBool) -> io::Result<()> {
+        let outputs = self.compaction.run_into(
+            self.output_level,
+            self.target_file_size,
+            &self.data_gen,
+            abandon,
+        )?;
+        self.outputs = match self.newest_gen {
+            Some(newest_gen) => outputs
+                .into_iter()
+                .map(|meta| levels::FileMeta { newest_gen, ..meta })
+                .collect(),
+            None => outputs,
+        };
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    Value,
+    Tombstone,
+}
+impl EntryKind {
+    pub fn as_byte(self) -> u8 {
+        match self {
+            EntryKind::Value => 0,
+            EntryKind::Tombstone => 1,
+        }
+    }
+    pub fn from_byte(byte: u8) -> Option<EntryKind> {
+        match byte {
+            0 => Some(EntryKind::Value),
+            1 => Some(EntryKind::Tombstone),
+            _ => None,
+        }
+    }
+}
+
 pub(crate) mod default {
-    use super::{data_file::*, index_file::*, *};
-    use crate::sst::memtable::{self, MemtableEntries};
-    use regex::Regex;
-    use std::{collections::HashMap, io};
+    use super::{bloom_filter::*, compaction::*, data_file::*, levels::*, manifest::*, *};
+    use crate::sst::{compression::Compression, options::CompactionStrategy, Options};
+    use std::{
+        collections::{HashMap, VecDeque},
+        io,
+        sync::atomic::Ordering,
+    };
 
     pub(crate) struct FileDisktable {
         dir_name: String,
-        data_gen: DataGen,
-        flushing: Option<MemtableEntries<String, String>>,
-        data_files: HashMap<DataGen, DataFile>,
+        // the latest generation ever reserved, which compactions take numbers from as well
+        data_gen: Arc<AtomicI32>,
+        // generations currently on disk and their levels
+        levels: Levels,
+        // log of changes on `levels`
+        manifest: Manifest,
+        compaction_threshold: Option<usize>,
+        compaction_strategy: CompactionStrategy,
+        data_file_options: DataFileOptions,
+        filter_counter: Arc<FilterCounter>,
+        // the largest key compacted last time in each level
+        compact_pointers: HashMap<usize, Vec<u8>>,
+        // memtables waiting to be flushed from oldest to newest, with generations reserved for them
+        immutables: VecDeque<(DataGen, QueuedMemtable)>,
+        // data files on disk, whose index and filter are loaded in memory
+        data_files: HashMap<DataGen, Arc<DataFile>>,
+        last_sequence: SeqNo,
+        snapshots: Arc<SnapshotList>,
     }
 
     impl FileDisktable {
-        pub fn new(dir_name: &str) -> Result<impl Disktable, io::Error> {
-            std::fs::create_dir_all(&dir_name).expect("failed to create directory");
-            let data_gen = Self::get_latest_data_gen(dir_name)?;
-            let flushing = None;
+        pub fn new(
+            dir_name: &str,
+            options: &Options,
+            snapshots: Arc<SnapshotList>,
+        ) -> Result<FileDisktable, io::Error> {
+            std::fs::create_dir_all(dir_name)?;
+            if let Compression::Custom(codec) = &options.compression {
+                if codec.id() < Compression::CUSTOM_ID_MIN {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("codec id {} is reserved for built-in codecs", codec.id()),
+                    ));
+                }
+            }
+            let (manifest, version) = Manifest::open(dir_name)?;
+            // files are kept until MANIFEST is replayed to the end,
+            // not to lose live ones when replay stopped earlier than expected
+            if !version.torn_tail {
+                Self::remove_obsolete_files(dir_name, &version)?;
+            }
+            let data_gens: Vec<_> = version.files.keys().copied().collect();
+            let data_files = Self::open_data_files(dir_name, &data_gens, &options.compression)?;
+            let levels = Levels::new(version.files);
 
             Ok(Self {
-                data_gen,
+                data_gen: Arc::new(AtomicI32::new(version.last_data_gen)),
+                levels,
+                manifest,
                 dir_name: dir_name.to_string(),
-                flushing,
-                data_files: HashMap::new(),
+                compaction_threshold: options.compaction_threshold,
+                compaction_strategy: options.compaction_strategy,
+                data_file_options: DataFileOptions {
+                    block_size: options.block_size,
+                    block_restart_interval: options.block_restart_interval,
+                    bloom_bits_per_key: options.bloom_bits_per_key,
+                    compression: options.compression.clone(),
+                },
+                filter_counter: Arc::new(FilterCounter::default()),
+                compact_pointers: HashMap::new(),
+                immutables: VecDeque::new(),
+                data_files,
+                last_sequence: version.last_sequence,
+                snapshots,
             })
         }
 
-        fn get_data_gens(dir_name: &str) -> io::Result<Vec<DataGen>> {
-            std::fs::read_dir(dir_name).map(|dir| {
-                let mut list = dir.fold(vec![], |mut acc, entry| {
-                    let file_name = entry.unwrap().file_name();
-                    let file_name = file_name.to_string_lossy();
-                    match Regex::new(&format!("{}_(?P<gen>\\d+)", DataFile::FILE_NAME_PREFIX))
-                        .unwrap()
-                        .captures(&file_name)
-                    {
-                        Some(cap) => {
-                            acc.push(cap["gen"].parse::<DataGen>().unwrap());
-                            acc
-                        }
-                        None => acc,
-                    }
-                });
-                list.sort();
-                list
-            })
+        // removes data files which MANIFEST doesn't know,
+        // which were written by a flush or a compaction interrupted before it was recorded
+        fn remove_obsolete_files(dir_name: &str, version: &Version) -> io::Result<()> {
+            for entry in std::fs::read_dir(dir_name)? {
+                let path = entry?.path();
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                let obsolete = file_name.starts_with(DataFile::TMP_FILE_PREFIX)
+                    || file_name
+                        .strip_prefix(DataFile::FILE_NAME_PREFIX)
+                        .and_then(|gen| gen.strip_prefix('_'))
+                        .and_then(|gen| gen.parse::<DataGen>().ok())
+                        .is_some_and(|gen| !version.files.contains_key(&gen));
+                if obsolete {
+                    log::info!("removing obsolete file {:?}", path);
+                    std::fs::remove_file(&path)?;
+                }
+            }
+            Ok(())
         }
 
-        fn get_latest_data_gen(dir_name: &str) -> io::Result<DataGen> {
-            Self::get_data_gens(dir_name).map(|list| *list.last().unwrap_or(&0))
+        fn open_data_files(
+            dir_name: &str,
+            data_gens: &[DataGen],
+            compression: &Compression,
+        ) -> io::Result<HashMap<DataGen, Arc<DataFile>>> {
+            data_gens
+                .iter()
+                .map(|gen| {
+                    DataFile::open(dir_name, *gen, compression)
+                        .map(|data_file| (*gen, Arc::new(data_file)))
+                })
+                .collect()
         }
 
-        fn with_data_file<T>(&self, gen: DataGen, f: impl Fn(&DataFile) -> T) -> T {
-          match self.data_files.get(&gen) {
-            Some(found) => f(found),
-            None => f(&DataFile::of(&self.dir_name, gen))
-          }
+        // records `edit` in MANIFEST first, and then applies it to generations in memory
+        fn log_and_apply(&mut self, edit: VersionEdit) -> io::Result<()> {
+            self.manifest.append(&edit)?;
+            edit.removed.iter().for_each(|gen| {
+                self.levels.remove(*gen);
+            });
+            let added_gens: Vec<_> = edit.added.iter().map(|m| m.data_gen).collect();
+            edit.added
+                .into_iter()
+                .for_each(|meta| self.levels.add(meta));
+            let opened = Self::open_data_files(
+                &self.dir_name,
+                &added_gens,
+                &self.data_file_options.compression,
+            )?;
+            self.data_files.extend(opened);
+            Ok(())
         }
 
-        fn index_file(&self, data_gen: DataGen) -> IndexFile {
-            IndexFile::of(data_gen, &self.dir_name)
+        // swaps inputs of compaction with its outputs, and then removes the inputs from disk
+        fn apply_compaction(
+            &mut self,
+            inputs: &[DataGen],
+            outputs: Vec<FileMeta>,
+        ) -> io::Result<()> {
+            // an output without any entry is dropped as well
+            let (outputs, empty): (Vec<_>, Vec<_>) =
+                outputs.into_iter().partition(|m| m.entry_count > 0);
+            self.log_and_apply(VersionEdit {
+                added: outputs,
+                removed: inputs.to_vec(),
+                last_data_gen: Some(self.data_gen.load(Ordering::SeqCst)),
+                last_sequence: None,
+            })?;
+            for gen in inputs.iter().chain(empty.iter().map(|m| &m.data_gen)) {
+                self.data_files.remove(gen);
+                DataFile::clear(&self.dir_name, *gen)?;
+            }
+            Ok(())
         }
 
-        fn fetch(&self, data_gen: DataGen, offset: Offset) -> Option<(String, String)> {
-            let entry = self.with_data_file(data_gen, |df| df.read_entry(offset));
-            entry.map(|entry| (entry.key, entry.value))
+        // `inputs` must be adjacent generations in level 0 in ascending order
+        fn size_tiered_job(&self, inputs: Vec<DataGen>) -> Option<CompactionJob> {
+            if inputs.is_empty() {
+                return None;
+            }
+            let inputs: Vec<_> = inputs.into_iter().rev().collect();
+            let oldest = self.levels.level(0).first().map(|m| m.data_gen);
+            let newest_gen = inputs
+                .iter()
+                .filter_map(|gen| self.levels.get(*gen))
+                .map(|m| m.newest_gen)
+                .max()
+                .unwrap_or_default();
+            Some(CompactionJob {
+                compaction: Compaction {
+                    dir_name: self.dir_name.clone(),
+                    drop_tombstones: inputs.last().copied() == oldest,
+                    oldest_snapshot: self.snapshots.oldest(),
+                    options: self.data_file_options.clone(),
+                    inputs,
+                },
+                output_level: 0,
+                target_file_size: u64::MAX,
+                newest_gen: Some(newest_gen),
+                data_gen: self.data_gen.clone(),
+                outputs: vec![],
+            })
         }
-    }
 
-    impl Disktable for FileDisktable {
-        fn find(&self, key: &str) -> Option<String> {
-            let find_from_disk = || {
-                (0..=self.data_gen).rev().find_map(|data_gen| {
-                    self.index_file(data_gen)
-                        .find_index(key)
-                        .and_then(|index_entry| {
-                            self.fetch(index_entry.data_gen, index_entry.offset)
-                                .filter(|(_key, _)| _key == key)
-                                .map(|(_, value)| value)
-                        })
-                })
+        // a generation with nothing to merge with is moved into the next level right away
+        fn leveled_job(
+            &mut self,
+            pick: LeveledPick,
+            target_file_size: u64,
+        ) -> io::Result<Option<CompactionJob>> {
+            let input_metas: Vec<FileMeta> = pick
+                .inputs
+                .iter()
+                .filter_map(|gen| self.levels.get(*gen).cloned())
+                .collect();
+            if let [meta] = input_metas.as_slice() {
+                if meta.level > 0 {
+                    self.log_and_apply(VersionEdit {
+                        added: vec![FileMeta {
+                            level: pick.output_level,
+                            ..meta.clone()
+                        }],
+                        removed: vec![meta.data_gen],
+                        last_data_gen: None,
+                        last_sequence: None,
+                    })?;
+                    return Ok(None);
+                }
+            }
+            let min_key = match input_metas.iter().map(|m| &m.min_key).min() {
+                Some(min_key) => min_key,
+                None => return Ok(None),
             };
-            match self.flushing.as_ref() {
-                Some(mem_entries) => match mem_entries.get(&key.to_string()) {
-                    memtable::GetResult::Found(value) => Some(value.to_string()),
-                    memtable::GetResult::Deleted => None,
-                    memtable::GetResult::NotFound => find_from_disk(),
+            let max_key = input_metas.iter().map(|m| &m.max_key).max().unwrap();
+            Ok(Some(CompactionJob {
+                compaction: Compaction {
+                    dir_name: self.dir_name.clone(),
+                    drop_tombstones: !self.levels.overlaps_below(
+                        pick.output_level,
+                        min_key,
+                        max_key,
+                    ),
+                    oldest_snapshot: self.snapshots.oldest(),
+                    options: self.data_file_options.clone(),
+                    inputs: pick.inputs,
                 },
-                None => find_from_disk(),
+                output_level: pick.output_level,
+                target_file_size,
+                newest_gen: None,
+                data_gen: self.data_gen.clone(),
+                outputs: vec![],
+            }))
+        }
+
+        fn due_compaction_job(&mut self) -> io::Result<Option<CompactionJob>> {
+            let threshold = match self.compaction_threshold {
+                Some(threshold) => threshold.max(2),
+                None => return Ok(None),
+            };
+            match self.compaction_strategy {
+                CompactionStrategy::SizeTiered => {
+                    let level0 = self.levels.level(0);
+                    if level0.len() < threshold {
+                        return Ok(None);
+                    }
+                    let gens: Vec<_> = level0.iter().map(|m| (m.data_gen, m.size)).collect();
+                    Ok(self.size_tiered_job(SizeTiered::pick(&gens)))
+                }
+                CompactionStrategy::Leveled(options) => {
+                    let leveled = Leveled {
+                        options,
+                        level0_threshold: threshold,
+                    };
+                    while let Some(pick) = leveled.pick(&self.levels, &mut self.compact_pointers) {
+                        if let Some(job) = self.leveled_job(pick, options.target_file_size)? {
+                            return Ok(Some(job));
+                        }
+                    }
+                    Ok(None)
+                }
             }
         }
+    }
 
-        fn flush(
-            &mut self,
-            memtable_entries: MemtableEntries<String, String>,
-        ) -> Result<(), io::Error> {
-            self.flushing = Some(memtable_entries);
-
-            let next_data_gen = self.data_gen + 1;
-            let new_data_file = DataFile::of(&self.dir_name, next_data_gen);
-            let new_index = new_data_file.create(self.flushing.as_ref().unwrap())?;
-            let new_index_file = IndexFile::of(next_data_gen, &self.dir_name);
-            new_index_file.create_index(&new_index)?;
-
-            self.data_gen = next_data_gen;
-            self.flushing = None;
-            log::trace!(
-                "Disktable#flush has completed. next_data_gen: {}",
-                next_data_gen
-            );
+    impl Disktable for FileDisktable {
+        fn lookup(&self, key: &[u8]) -> KeyLookup {
+            KeyLookup {
+                memtables: self
+                    .immutables
+                    .iter()
+                    .rev()
+                    .map(|(_, memtable)| memtable.entries.clone())
+                    .collect(),
+                data_files: self
+                    .levels
+                    .lookup_order(key)
+                    .into_iter()
+                    .filter_map(|gen| self.data_files.get(&gen).cloned())
+                    .collect(),
+                filter_counter: self.filter_counter.clone(),
+            }
+        }
+
+        fn schedule_flush(&mut self, memtable: QueuedMemtable) {
+            let data_gen = self.data_gen.fetch_add(1, Ordering::SeqCst) + 1;
+            self.immutables.push_back((data_gen, memtable));
+        }
+
+        fn flush_job(&self) -> Option<FlushJob> {
+            let (data_gen, memtable) = self.immutables.front()?;
+            Some(FlushJob {
+                dir_name: self.dir_name.clone(),
+                data_gen: *data_gen,
+                entries: memtable.entries.clone(),
+                oldest_snapshot: self.snapshots.oldest(),
+                options: self.data_file_options.clone(),
+                written: None,
+            })
+        }
+
+        fn install_flush(&mut self, job: FlushJob) -> Result<(), io::Error> {
+            let FlushJob {
+                data_gen,
+                entries,
+                written,
+                ..
+            } = job;
+            let meta = written.expect("flush job has not run");
+            let queued = self
+                .immutables
+                .front()
+                .is_some_and(|(queued_gen, memtable)| {
+                    *queued_gen == data_gen && Arc::ptr_eq(&memtable.entries, &entries)
+                });
+            if !queued {
+                // the memtable was dropped by `clear` while being written
+                return DataFile::clear(&self.dir_name, data_gen);
+            }
+            let last_sequence = entries
+                .iter()
+                .map(|(_, seq, _)| seq)
+                .max()
+                .unwrap_or_default()
+                .max(self.last_sequence);
+            self.log_and_apply(VersionEdit {
+                added: vec![meta],
+                removed: vec![],
+                last_data_gen: Some(self.data_gen.load(Ordering::SeqCst)),
+                last_sequence: Some(last_sequence),
+            })?;
+            self.last_sequence = last_sequence;
+            let (_, memtable) = self.immutables.pop_front().unwrap();
+            memtable.persisted()?;
+            log::trace!("Disktable#flush has completed. data_gen: {}", data_gen);
             Ok(())
         }
 
+        fn queued_flushes(&self) -> usize {
+            self.immutables.len()
+        }
+
+        fn compaction_job(&mut self, full: bool) -> Result<Option<CompactionJob>, io::Error> {
+            if !full {
+                return self.due_compaction_job();
+            }
+            match self.compaction_strategy {
+                CompactionStrategy::SizeTiered => {
+                    let gens = self.levels.level(0).iter().map(|m| m.data_gen).collect();
+                    Ok(self.size_tiered_job(gens))
+                }
+                CompactionStrategy::Leveled(options) => {
+                    let pick = Leveled::pick_all(&self.levels);
+                    self.leveled_job(pick, options.target_file_size)
+                }
+            }
+        }
+
+        fn install_compaction(&mut self, job: CompactionJob) -> Result<(), io::Error> {
+            self.apply_compaction(&job.compaction.inputs, job.outputs)
+        }
+
+        fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> EntryIter {
+            let mut sources: Vec<EntryIter> = vec![];
+            let (start_vec, end_vec) = (start.map(<[u8]>::to_vec), end.map(<[u8]>::to_vec));
+            for (_, memtable) in self.immutables.iter().rev() {
+                sources.push(Box::new(
+                    memtable
+                        .entries
+                        .shared_range(start_vec.as_ref(), end_vec.as_ref())
+                        .map(|(key, seq, value)| Ok(DataEntry::new(key, seq, value))),
+                ));
+            }
+            for data_gen in self.levels.scan_order(start, end) {
+                if let Some(data_file) = self.data_files.get(&data_gen) {
+                    sources.push(Box::new(data_file.entries_from(start)));
+                }
+            }
+            Box::new(MergingIter::new(sources))
+        }
+
+        fn cursors(&self) -> Vec<Box<dyn RawCursor + Send>> {
+            let mut cursors: Vec<Box<dyn RawCursor + Send>> = vec![];
+            for (_, memtable) in self.immutables.iter().rev() {
+                cursors.push(Box::new(memtable.entries.clone()));
+            }
+            for data_gen in self.levels.scan_order(Bound::Unbounded, Bound::Unbounded) {
+                if let Some(data_file) = self.data_files.get(&data_gen) {
+                    cursors.push(Box::new(data_file.cursor()));
+                }
+            }
+            cursors
+        }
+
         fn clear(&mut self) -> Result<(), io::Error> {
-            (0..=self.data_gen).for_each(|gen| {
-                DataFile::clear(&self.dir_name, gen).unwrap();
-                IndexFile::clear(gen, &self.dir_name).unwrap();
-            });
-            self.data_gen = 0;
+            for (_, memtable) in self.immutables.drain(..) {
+                memtable.discard()?;
+            }
+            // generation numbers and sequence numbers are never reused.
+            // files are removed after MANIFEST forgets them, as obsolete ones are at open.
+            self.manifest.reset(&Version {
+                last_data_gen: self.data_gen.load(Ordering::SeqCst),
+                last_sequence: self.last_sequence,
+                ..Version::default()
+            })?;
+            let data_gens = self.levels.data_gens();
+            self.data_files.clear();
+            self.levels.clear();
+            for gen in data_gens {
+                DataFile::clear(&self.dir_name, gen)?;
+            }
             Ok(())
         }
+
+        fn filter_stats(&self) -> FilterStats {
+            self.filter_counter.stats()
+        }
+
+        fn last_sequence(&self) -> SeqNo {
+            self.last_sequence
+        }
     }
 }
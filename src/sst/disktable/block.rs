@@ -0,0 +1,231 @@
+use super::*;
+use crate::sst::byte_utils::*;
+
+/*
+Block layout:
+[shared key len][unshared key len][value len][sequence][entry kind][unshared key][value]
+<---varint-----><----varint------><-varint--><-varint-><--1 byte--><------------><----->
+...
+[restart point]...[number of restart points]
+<---4 byte----->   <--------4 byte--------->
+
+Keys in a block are sorted, and versions of the same key are sorted from newest to oldest
+by their sequence numbers. A key is stored as the length of the prefix shared
+with the previous key followed by the rest of it.
+At every restart point a key is stored as is, so that a lookup can binary-search
+restart points and then decode only a few entries after the found one.
+*/
+pub(crate) struct BlockBuilder {
+    buf: Vec<u8>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    counter: usize,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    pub fn new(restart_interval: usize) -> BlockBuilder {
+        BlockBuilder {
+            buf: vec![],
+            restarts: vec![0],
+            restart_interval: restart_interval.max(1),
+            counter: 0,
+            last_key: vec![],
+        }
+    }
+
+    /// `key` must not be smaller than any key added so far,
+    /// and the same key must be added with a smaller `seq` than before.
+    pub fn add(&mut self, key: &[u8], seq: SeqNo, kind: EntryKind, value: &[u8]) {
+        let shared = if self.counter < self.restart_interval {
+            self.last_key
+                .iter()
+                .zip(key.iter())
+                .take_while(|(a, b)| a == b)
+                .count()
+        } else {
+            self.restarts.push(self.buf.len() as u32);
+            self.counter = 0;
+            0
+        };
+        ByteUtils::put_varint(&mut self.buf, shared as u64);
+        ByteUtils::put_varint(&mut self.buf, (key.len() - shared) as u64);
+        ByteUtils::put_varint(&mut self.buf, value.len() as u64);
+        ByteUtils::put_varint(&mut self.buf, seq);
+        self.buf.push(kind.as_byte());
+        self.buf.extend_from_slice(&key[shared..]);
+        self.buf.extend_from_slice(value);
+
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.counter += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn estimated_size(&self) -> usize {
+        self.buf.len() + (self.restarts.len() + 1) * 4
+    }
+
+    pub fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    /// Returns block contents, and resets this builder for the next block.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut contents = std::mem::take(&mut self.buf);
+        self.restarts.iter().for_each(|restart| {
+            contents.extend_from_slice(&ByteUtils::from_usize(*restart as usize));
+        });
+        contents.extend_from_slice(&ByteUtils::from_usize(self.restarts.len()));
+        self.restarts = vec![0];
+        self.counter = 0;
+        self.last_key.clear();
+        contents
+    }
+}
+
+pub(crate) struct BlockEntry {
+    pub key: Vec<u8>,
+    pub seq: SeqNo,
+    pub kind: EntryKind,
+    pub value: Vec<u8>,
+}
+
+pub(crate) struct Block {
+    data: Vec<u8>,
+    // where restart points start, which is also the end of entries
+    restarts_offset: usize,
+    num_restarts: usize,
+}
+
+impl Block {
+    pub fn new(data: Vec<u8>) -> io::Result<Block> {
+        let broken = || corrupted("block is too short to hold restart points");
+        if data.len() < 4 {
+            return Err(broken());
+        }
+        let num_restarts = ByteUtils::as_usize(&data[(data.len() - 4)..]);
+        let restarts_offset = data
+            .len()
+            .checked_sub(4 + num_restarts * 4)
+            .ok_or_else(broken)?;
+        Ok(Block {
+            data,
+            restarts_offset,
+            num_restarts,
+        })
+    }
+
+    fn restart_point(&self, index: usize) -> usize {
+        let offset = self.restarts_offset + index * 4;
+        ByteUtils::as_usize(&self.data[offset..(offset + 4)])
+    }
+
+    // decodes an entry at `offset` following `last_key`, and returns the offset of next entry
+    fn decode_at(&self, offset: usize, last_key: &[u8]) -> io::Result<(BlockEntry, usize)> {
+        let broken = || corrupted(format!("broken entry at offset {} in block", offset));
+        let entries = &self.data[..self.restarts_offset];
+        let mut pos = offset;
+        let varint = |pos: &mut usize| {
+            let (n, read) = entries
+                .get(*pos..)
+                .and_then(ByteUtils::get_varint)
+                .ok_or_else(broken)?;
+            *pos += read;
+            Ok::<u64, io::Error>(n)
+        };
+        let shared = varint(&mut pos)? as usize;
+        let unshared = varint(&mut pos)? as usize;
+        let value_len = varint(&mut pos)? as usize;
+        let seq = varint(&mut pos)?;
+        let kind = entries
+            .get(pos)
+            .and_then(|byte| EntryKind::from_byte(*byte))
+            .ok_or_else(broken)?;
+        pos += 1;
+        let unshared_key = entries.get(pos..(pos + unshared)).ok_or_else(broken)?;
+        pos += unshared;
+        let value = entries.get(pos..(pos + value_len)).ok_or_else(broken)?;
+        pos += value_len;
+
+        let mut key = last_key.get(..shared).ok_or_else(broken)?.to_vec();
+        key.extend_from_slice(unshared_key);
+        Ok((
+            BlockEntry {
+                key,
+                seq,
+                kind,
+                value: value.to_vec(),
+            },
+            pos,
+        ))
+    }
+
+    pub fn iter(&self) -> BlockIter<'_> {
+        BlockIter {
+            block: self,
+            offset: 0,
+            last_key: vec![],
+        }
+    }
+
+    /// Finds the first entry whose key is equal to or larger than `key`,
+    /// which is the newest version if the key is found.
+    pub fn seek(&self, key: &[u8]) -> io::Result<Option<BlockEntry>> {
+        // the last restart point whose key is smaller than `key`
+        let (mut left, mut right) = (0, self.num_restarts);
+        while right - left > 1 {
+            let mid = (left + right) / 2;
+            let (entry, _) = self.decode_at(self.restart_point(mid), &[])?;
+            if entry.key.as_slice() < key {
+                left = mid;
+            } else {
+                right = mid;
+            }
+        }
+        let mut iter = BlockIter {
+            block: self,
+            offset: if self.num_restarts > 0 {
+                self.restart_point(left)
+            } else {
+                self.restarts_offset
+            },
+            last_key: vec![],
+        };
+        for entry in iter.by_ref() {
+            let entry = entry?;
+            if entry.key.as_slice() >= key {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub(crate) struct BlockIter<'a> {
+    block: &'a Block,
+    offset: usize,
+    last_key: Vec<u8>,
+}
+impl Iterator for BlockIter<'_> {
+    type Item = io::Result<BlockEntry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.block.restarts_offset {
+            return None;
+        }
+        match self.block.decode_at(self.offset, &self.last_key) {
+            Ok((entry, next)) => {
+                self.offset = next;
+                self.last_key = entry.key.clone();
+                Some(Ok(entry))
+            }
+            Err(err) => {
+                self.offset = self.block.restarts_offset;
+                Some(Err(err))
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/*
+Bloom filter built over keys of a data generation.
+It is used to skip reading data blocks of a generation which never contains a key.
+
+filter layout:
+[number of probes][bit array]
+<----1 byte------><--------->
+*/
+pub(crate) struct BloomFilter {
+    num_probes: u8,
+    bits: Vec<u8>,
+}
+
+impl BloomFilter {
+    /// Builds a filter over keys given by their `hash`es,
+    /// so that the keys need not be kept until the number of them is known.
+    pub fn build(hashes: &[u32], bits_per_key: usize) -> Self {
+        // ln(2) * bits_per_key minimizes the false positive rate
+        let num_probes = ((bits_per_key as f64 * 0.69) as u8).clamp(1, 30);
+        // too small filter causes too many false positives
+        let num_bits = (hashes.len() * bits_per_key).max(64);
+        let mut filter = BloomFilter {
+            num_probes,
+            bits: vec![0; num_bits.div_ceil(8)],
+        };
+        hashes.iter().for_each(|hash| {
+            filter.probes(*hash).for_each(|bit| {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            })
+        });
+        filter
+    }
+
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.probes(Self::hash(key))
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    // double hashing to derive `num_probes` bit positions from a hash value
+    fn probes(&self, mut hash: u32) -> impl Iterator<Item = usize> {
+        let num_bits = self.bits.len() * 8;
+        let delta = hash.rotate_right(17);
+        (0..self.num_probes).map(move |_| {
+            let bit = hash as usize % num_bits;
+            hash = hash.wrapping_add(delta);
+            bit
+        })
+    }
+
+    // murmur-like hash, same as the one in LevelDB
+    pub fn hash(data: &[u8]) -> u32 {
+        const SEED: u32 = 0xbc9f1d34;
+        const M: u32 = 0xc6a4a793;
+        let mut h = SEED ^ (data.len() as u32).wrapping_mul(M);
+        let mut chunks = data.chunks_exact(4);
+        chunks.by_ref().for_each(|chunk| {
+            let w = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            h = h.wrapping_add(w).wrapping_mul(M);
+            h ^= h >> 16;
+        });
+        let rest = chunks.remainder();
+        if !rest.is_empty() {
+            rest.iter()
+                .enumerate()
+                .for_each(|(i, byte)| h = h.wrapping_add((*byte as u32) << (8 * i)));
+            h = h.wrapping_mul(M);
+            h ^= h >> 24;
+        }
+        h
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&[self.num_probes][..], &self.bits].concat()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes.split_first() {
+            Some((num_probes, bits)) if !bits.is_empty() => Some(BloomFilter {
+                num_probes: *num_probes,
+                bits: bits.to_vec(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Statistics of lookups through Bloom filters.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FilterStats {
+    /// number of times a filter was consulted
+    pub checked: u64,
+    /// number of times a filter said a key is absent, and so a generation was skipped
+    pub skipped: u64,
+    /// number of times a filter said a key may be present, but it was absent
+    pub false_positives: u64,
+}
+impl FilterStats {
+    /// Ratio of false positives among lookups of keys absent from a generation.
+    pub fn false_positive_rate(&self) -> f64 {
+        let negatives = self.skipped + self.false_positives;
+        if negatives == 0 {
+            0.0
+        } else {
+            self.false_positives as f64 / negatives as f64
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct FilterCounter {
+    checked: AtomicU64,
+    skipped: AtomicU64,
+    false_positives: AtomicU64,
+}
+impl FilterCounter {
+    pub fn checked(&self, may_contain: bool) {
+        self.checked.fetch_add(1, Ordering::Relaxed);
+        if !may_contain {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    pub fn false_positive(&self) {
+        self.false_positives.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn stats(&self) -> FilterStats {
+        FilterStats {
+            checked: self.checked.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            false_positives: self.false_positives.load(Ordering::Relaxed),
+        }
+    }
+}
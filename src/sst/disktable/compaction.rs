@@ -0,0 +1,325 @@
+use super::{data_file::*, levels::*, *};
+use crate::sst::options::LeveledOptions;
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    iter::Peekable,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc,
+    },
+};
+
+/// Writes sorted `entries` into a new generation in `level`.
+pub(crate) fn write_generation(
+    dir_name: &str,
+    data_gen: DataGen,
+    level: usize,
+    entries: impl IntoIterator<Item = io::Result<DataEntry>>,
+    options: &DataFileOptions,
+) -> io::Result<FileMeta> {
+    let footer = DataFile::create(dir_name, data_gen, entries, options).inspect_err(|_| {
+        // not to leave a half-written file behind
+        let _ = std::fs::remove_file(DataFile::tmp_path(dir_name, data_gen));
+    })?;
+    Ok(FileMeta {
+        data_gen,
+        level,
+        newest_gen: data_gen,
+        size: std::fs::metadata(DataFile::path(dir_name, data_gen))?.len(),
+        entry_count: footer.entry_count,
+        min_key: footer.min_key,
+        max_key: footer.max_key,
+    })
+}
+
+/// Compaction merges several data generations into sorted generation(s).
+pub(crate) struct Compaction {
+    pub dir_name: String,
+    // generations to be merged, from newest to oldest
+    pub inputs: Vec<DataGen>,
+    // tombstones are obsolete only when no older generation may hold the same key
+    pub drop_tombstones: bool,
+    // versions visible to the oldest snapshot are kept
+    pub oldest_snapshot: SeqNo,
+    pub options: DataFileOptions,
+}
+
+impl Compaction {
+    fn merge(&self, data_files: &[Arc<DataFile>]) -> impl Iterator<Item = io::Result<DataEntry>> {
+        let sources = data_files.iter().map(|df| df.entries()).collect();
+        retain_visible(
+            MergingIter::new(sources),
+            self.oldest_snapshot,
+            self.drop_tombstones,
+        )
+    }
+
+    fn data_files(&self) -> io::Result<Vec<Arc<DataFile>>> {
+        self.inputs
+            .iter()
+            .map(|gen| {
+                DataFile::open(&self.dir_name, *gen, &self.options.compression).map(Arc::new)
+            })
+            .collect()
+    }
+
+    /// Merges inputs into new generations in `level`,
+    /// each of which is split when its size reaches `target_file_size`.
+    /// Generations are numbered by `data_gen`, which is shared with flushes.
+    /// Once `abandon` is set, it stops and removes the outputs written so far.
+    pub fn run_into(
+        &self,
+        level: usize,
+        target_file_size: u64,
+        data_gen: &AtomicI32,
+        abandon: &AtomicBool,
+    ) -> io::Result<Vec<FileMeta>> {
+        let mut outputs = vec![];
+        self.write_outputs(level, target_file_size, data_gen, abandon, &mut outputs)
+            .inspect_err(|_| {
+                outputs.iter().for_each(|meta| {
+                    let _ = DataFile::clear(&self.dir_name, meta.data_gen);
+                });
+            })?;
+        log::trace!(
+            "Compaction has completed. inputs: {:?}, outputs: {:?}",
+            self.inputs,
+            outputs.iter().map(|m| m.data_gen).collect::<Vec<_>>()
+        );
+        Ok(outputs)
+    }
+
+    fn write_outputs(
+        &self,
+        level: usize,
+        target_file_size: u64,
+        data_gen: &AtomicI32,
+        abandon: &AtomicBool,
+        outputs: &mut Vec<FileMeta>,
+    ) -> io::Result<()> {
+        let data_files = self.data_files()?;
+        let mut merged = self
+            .merge(&data_files)
+            .map(|entry| match abandon.load(Ordering::Relaxed) {
+                true => Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    "compaction was abandoned",
+                )),
+                false => entry,
+            })
+            .peekable();
+        while merged.peek().is_some() {
+            let output_gen = data_gen.fetch_add(1, Ordering::SeqCst) + 1;
+            let mut written = 0;
+            let mut last_key = vec![];
+            let chunk = std::iter::from_fn(|| {
+                // versions of a key are never split into different generations
+                let same_key = matches!(merged.peek(), Some(Ok(entry)) if entry.key == last_key);
+                if written >= target_file_size && !same_key {
+                    return None;
+                }
+                merged.next().map(|entry| {
+                    entry.inspect(|entry| {
+                        written += entry.size as u64;
+                        last_key.clone_from(&entry.key);
+                    })
+                })
+            });
+            outputs.push(write_generation(
+                &self.dir_name,
+                output_gen,
+                level,
+                chunk,
+                &self.options,
+            )?);
+        }
+        Ok(())
+    }
+}
+
+/// Drops versions which no reader can see any longer from `entries` sorted by key and
+/// then from newest to oldest, i.e. versions shadowed by a newer one visible to `oldest_snapshot`.
+/// Tombstones visible to `oldest_snapshot` are dropped as well if `drop_tombstones`.
+pub(crate) fn retain_visible(
+    entries: impl Iterator<Item = io::Result<DataEntry>>,
+    oldest_snapshot: SeqNo,
+    drop_tombstones: bool,
+) -> impl Iterator<Item = io::Result<DataEntry>> {
+    let mut last_key: Option<Vec<u8>> = None;
+    // whether a newer version of the same key is visible to `oldest_snapshot`
+    let mut shadowed = false;
+    entries.filter(move |entry| {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => return true,
+        };
+        if last_key.as_ref() != Some(&entry.key) {
+            last_key = Some(entry.key.clone());
+            shadowed = false;
+        }
+        let visible = entry.seq <= oldest_snapshot;
+        let retained = !(shadowed || drop_tombstones && visible && entry.value.is_none());
+        shadowed |= visible;
+        retained
+    })
+}
+
+/// Merges sorted entries of data files into the order by key, and then from newest to oldest.
+/// Every version is yielded, and the same version in several sources is yielded only once.
+pub(crate) struct MergingIter<I: Iterator<Item = io::Result<DataEntry>>> {
+    sources: Vec<Peekable<I>>,
+}
+impl<I: Iterator<Item = io::Result<DataEntry>>> MergingIter<I> {
+    pub fn new(sources: Vec<I>) -> MergingIter<I> {
+        MergingIter {
+            sources: sources.into_iter().map(|s| s.peekable()).collect(),
+        }
+    }
+}
+impl<I: Iterator<Item = io::Result<DataEntry>>> Iterator for MergingIter<I> {
+    type Item = io::Result<DataEntry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        // an error is surfaced as soon as any source hits it
+        for source in self.sources.iter_mut() {
+            if let Some(Err(_)) = source.peek() {
+                return source.next();
+            }
+        }
+        let (min_key, max_seq) = self
+            .sources
+            .iter_mut()
+            .filter_map(|source| match source.peek() {
+                Some(Ok(entry)) => Some((entry.key.clone(), Reverse(entry.seq))),
+                _ => None,
+            })
+            .min()?;
+        let is_next = |entry: &io::Result<DataEntry>| matches!(entry, Ok(entry) if entry.key == min_key && entry.seq == max_seq.0);
+        self.sources
+            .iter_mut()
+            .fold(None, |next, source| match source.next_if(is_next) {
+                Some(entry) => next.or(Some(entry)),
+                None => next,
+            })
+    }
+}
+
+/*
+Size-tiered strategy:
+adjacent generations whose data file sizes are similar are grouped into a bucket,
+and the largest bucket is picked to be merged.
+When every generation falls into its own bucket, all of them are picked.
+*/
+pub(crate) struct SizeTiered;
+impl SizeTiered {
+    const BUCKET_LOW: f64 = 0.5;
+    const BUCKET_HIGH: f64 = 1.5;
+    // data files smaller than this are regarded as the same tier
+    const MIN_FILE_SIZE: u64 = 4 * 1024;
+
+    pub fn pick(gens: &[(DataGen, u64)]) -> Vec<DataGen> {
+        let mut buckets: Vec<Vec<(DataGen, u64)>> = vec![];
+        gens.iter().for_each(|(gen, size)| {
+            let fits = buckets.last().is_some_and(|bucket| {
+                let avg =
+                    bucket.iter().map(|(_, size)| *size).sum::<u64>() as f64 / bucket.len() as f64;
+                let size = *size as f64;
+                (size >= avg * Self::BUCKET_LOW && size <= avg * Self::BUCKET_HIGH)
+                    || (size < Self::MIN_FILE_SIZE as f64 && avg < Self::MIN_FILE_SIZE as f64)
+            });
+            match buckets.last_mut() {
+                Some(bucket) if fits => bucket.push((*gen, *size)),
+                _ => buckets.push(vec![(*gen, *size)]),
+            }
+        });
+        buckets
+            .into_iter()
+            .filter(|bucket| bucket.len() >= 2)
+            .max_by_key(|bucket| bucket.len())
+            .map(|bucket| bucket.into_iter().map(|(gen, _)| gen).collect())
+            .unwrap_or_else(|| gens.iter().map(|(gen, _)| *gen).collect())
+    }
+}
+
+/*
+Leveled strategy:
+- once level 0 holds `level0_threshold` generations,
+  all of them are merged with overlapping generations in level 1.
+- once level N(>0) exceeds its size budget, one generation in level N is picked
+  in round-robin order of keys and merged with overlapping generations in level N+1.
+*/
+pub(crate) struct Leveled {
+    pub options: LeveledOptions,
+    pub level0_threshold: usize,
+}
+pub(crate) struct LeveledPick {
+    // from newest to oldest
+    pub inputs: Vec<DataGen>,
+    pub output_level: usize,
+}
+impl Leveled {
+    fn level_budget(&self, level: usize) -> u64 {
+        (1..level).fold(self.options.max_bytes_for_level_base, |budget, _| {
+            budget.saturating_mul(self.options.max_bytes_for_level_multiplier)
+        })
+    }
+
+    // generations in `level` overlapping with the key range of `files`
+    fn overlapping(levels: &Levels, level: usize, files: &[&FileMeta]) -> Vec<DataGen> {
+        let min_key = files.iter().map(|m| &m.min_key).min();
+        let max_key = files.iter().map(|m| &m.max_key).max();
+        match (min_key, max_key) {
+            (Some(min_key), Some(max_key)) => levels
+                .level(level)
+                .iter()
+                .filter(|m| m.overlaps(min_key, max_key))
+                .map(|m| m.data_gen)
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn pick(
+        &self,
+        levels: &Levels,
+        compact_pointers: &mut HashMap<usize, Vec<u8>>,
+    ) -> Option<LeveledPick> {
+        let level0 = levels.level(0);
+        if level0.len() >= self.level0_threshold {
+            let mut inputs: Vec<_> = level0.iter().rev().map(|m| m.data_gen).collect();
+            inputs.extend(Self::overlapping(levels, 1, &level0));
+            return Some(LeveledPick {
+                inputs,
+                output_level: 1,
+            });
+        }
+        (1..Levels::MAX_LEVEL)
+            .find(|level| levels.level_size(*level) > self.level_budget(*level))
+            .map(|level| {
+                let files = levels.level(level);
+                let picked = compact_pointers
+                    .get(&level)
+                    .and_then(|pointer| files.iter().find(|m| m.min_key > *pointer))
+                    .unwrap_or(&files[0]);
+                compact_pointers.insert(level, picked.max_key.clone());
+                let mut inputs = vec![picked.data_gen];
+                inputs.extend(Self::overlapping(levels, level + 1, &[picked]));
+                LeveledPick {
+                    inputs,
+                    output_level: level + 1,
+                }
+            })
+    }
+
+    /// Picks all generations to be merged into the deepest level.
+    pub fn pick_all(levels: &Levels) -> LeveledPick {
+        let mut inputs: Vec<_> = levels.level(0).iter().rev().map(|m| m.data_gen).collect();
+        (1..=levels.max_level()).for_each(|level| {
+            inputs.extend(levels.level(level).iter().map(|m| m.data_gen));
+        });
+        LeveledPick {
+            inputs,
+            output_level: levels.max_level().max(1),
+        }
+    }
+}
@@ -1,119 +1,620 @@
 use super::*;
-use crate::sst::rich_file::*;
-use byte_utils::*;
-use io::{BufWriter, Read, Seek, SeekFrom, Write};
-use std::slice::SliceIndex;
+use crate::sst::byte_utils::*;
+use crate::sst::{
+    compression::Compression,
+    iter::{Direction, RawCursor},
+    rich_file::*,
+};
+use block::*;
+use bloom_filter::BloomFilter;
+use io::{BufWriter, Write};
+use std::{ops::Bound, path::PathBuf, sync::Arc};
 
-pub(crate) struct DataFile {
-    pub data_gen: DataGen,
-    pub file: RichFile,
+#[derive(Clone, Debug)]
+pub(crate) struct DataFileOptions {
+    pub block_size: usize,
+    pub block_restart_interval: usize,
+    pub bloom_bits_per_key: Option<usize>,
+    pub compression: Compression,
 }
+
+#[derive(Clone)]
 pub(crate) struct DataEntry {
-    pub data_gen: DataGen,
-    pub offset: Offset,
+    // approximate bytes occupied in data file
     pub size: usize,
-    pub key_len: usize,
-    pub value_len: usize,
-    pub key: String,
-    pub value: String,
+    pub key: Vec<u8>,
+    pub seq: SeqNo,
+    // `None` if the entry is a tombstone
+    pub value: Option<Vec<u8>>,
+}
+/// What `DataFile::find` found for a key.
+pub(crate) enum Lookup {
+    Found(DataEntry),
+    // the key is stored, but only in versions newer than the read
+    Invisible,
+    Absent,
+}
+
+impl DataEntry {
+    pub fn new(key: Vec<u8>, seq: SeqNo, value: Option<Vec<u8>>) -> DataEntry {
+        DataEntry {
+            size: key.len() + value.as_ref().map_or(0, Vec::len),
+            key,
+            seq,
+            value,
+        }
+    }
+}
+
+// where a block is in data file
+#[derive(Clone, Copy, Debug, Default)]
+struct BlockHandle {
+    offset: Offset,
+    // excluding checksum
+    size: u64,
+}
+impl BlockHandle {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        ByteUtils::put_varint(buf, self.offset);
+        ByteUtils::put_varint(buf, self.size);
+    }
+    fn decode(bytes: &[u8]) -> Option<(BlockHandle, usize)> {
+        let (offset, read1) = ByteUtils::get_varint(bytes)?;
+        let (size, read2) = ByteUtils::get_varint(bytes.get(read1..)?)?;
+        Some((BlockHandle { offset, size }, read1 + read2))
+    }
+}
+
+pub(crate) struct Footer {
+    pub entry_count: u64,
+    pub min_key: Vec<u8>,
+    pub max_key: Vec<u8>,
+    // id of the codec which compressed data blocks
+    compression: u8,
+    filter_handle: BlockHandle,
+    index_handle: BlockHandle,
+}
+
+/*
+Data file Layout:
+[magic][version]
+<4byte><-1byte->
+[data block][checksum]
+<----------><-4 byte->
+...
+[filter block][checksum]
+[index block][checksum]
+[entry count][min key length][min key][max key length][max key][compression][filter handle][index handle]
+<--8 byte---><---4 byte-----><-------><---4 byte-----><-------><--1 byte---><--2 varints--><--2 varints->
+[footer offset][footer checksum][magic]
+<---8 byte----><----4 byte-----><4byte>
+
+- data blocks hold entries in key order, and versions of a key from newest to oldest, compressed by the codec of `compression`. See `Block` for its layout.
+- filter block is Bloom filter over all keys. It is empty when the filter is disabled.
+- index block maps the last key of each data block to the offset and size of the block.
+- checksum is CRC32C of a block or the footer.
+*/
+pub(crate) struct DataFile {
+    pub file: RichFile,
+    // the last key of each data block, and where the block is
+    index: Vec<(Vec<u8>, BlockHandle)>,
+    filter: Option<BloomFilter>,
+    compression: Compression,
 }
 
 impl DataFile {
     pub const FILE_NAME_PREFIX: &'static str = "data";
+    // a data file is written into `tmp_data_N` first, and then renamed
+    pub const TMP_FILE_PREFIX: &'static str = "tmp_data";
+    const MAGIC: &'static [u8; 4] = b"RSST";
+    const VERSION: u8 = 3;
+    const HEADER_SIZE: u64 = 5;
+    const TRAILER_SIZE: u64 = 16;
+
+    fn file_name(data_gen: DataGen) -> String {
+        format!("{}_{}", DataFile::FILE_NAME_PREFIX, data_gen)
+    }
+
+    pub fn path(dir_name: &str, data_gen: DataGen) -> PathBuf {
+        std::path::Path::new(dir_name).join(Self::file_name(data_gen))
+    }
+
+    pub fn tmp_path(dir_name: &str, data_gen: DataGen) -> PathBuf {
+        std::path::Path::new(dir_name).join(Self::tmp_file_name(data_gen))
+    }
+
+    fn tmp_file_name(data_gen: DataGen) -> String {
+        format!("{}_{}", DataFile::TMP_FILE_PREFIX, data_gen)
+    }
 
-    pub fn of(dir_name: &str, data_gen: DataGen) -> DataFile {
-        let file = RichFile::open_file(
-            dir_name,
-            format!("{}_{}", DataFile::FILE_NAME_PREFIX, data_gen),
-            FileOption::Append,
-        )
-        .expect("failed to open data file");
-
-        DataFile { data_gen, file }
-    }
-    /*
-    Data Layout:
-    [key length][value length][ key data  ][value data ]\0
-    <--4 byte--><--4 byte----><--key_len--><-value_len->
-    */
-    pub fn read_entry(&self, offset: Offset) -> Option<DataEntry> {
-        let mut data = &self.file.underlying;
-        data.seek(SeekFrom::Start(offset)).unwrap();
-        let mut size: [u8; 4] = [0; 4];
-        let res = data.read_exact(&mut size);
-        if res.is_err() {
-            return None;
-        }
-        let size = ByteUtils::as_usize(&size);
-        let mut bytes = vec![0u8; size - 4];
-        let res = data.read_exact(&mut bytes);
-        if res.is_err() {
-            return None;
-        }
-        let expected = |prefix, range| {
-          format!("[{}]failed to read bytes({:?}) for range({:?})", prefix, bytes, range)
+    /// Opens a data file, and loads its index and filter in memory.
+    /// `compression` is used to resolve a custom codec recorded in the file.
+    pub fn open(
+        dir_name: &str,
+        data_gen: DataGen,
+        compression: &Compression,
+    ) -> io::Result<DataFile> {
+        // a missing data file is reported as an error instead of a panic in `RichFile::open_file`
+        let file = RichFile {
+            underlying: std::fs::File::open(Self::path(dir_name, data_gen))?,
+            dir: dir_name.to_string(),
+            name: Self::file_name(data_gen),
         };
-        let key_len_bytes = bytes.get(0..4).unwrap_or_else(|| panic!(expected("key_len", 0..4)));
-        let key_len = ByteUtils::as_usize(key_len_bytes);
-        let value_len_bytes = bytes.get(4..8).unwrap_or_else(|| panic!(expected("value_len", 4..8)));
-        let value_len = ByteUtils::as_usize(value_len_bytes);
-        let key_data = bytes.get(8..(8 + key_len)).unwrap_or_else(|| panic!(expected("key_data", 8..(8 + key_len))));
-        let value_data = bytes.get((8 + key_len)..(8 + key_len + value_len)).unwrap_or_else(
-          || panic!(expected("value_data", (8 + key_len)..(8 + key_len + value_len)))
-        );
-        Some(DataEntry {
-            data_gen: self.data_gen,
-            offset,
-            size,
-            key_len,
-            value_len,
-            key: ByteUtils::as_string(&key_data),
-            value: ByteUtils::as_string(&value_data),
+        Self::check_header(&file)?;
+        let footer = Self::read_footer(&file)?;
+        let index = Self::read_block(&file, footer.index_handle)?
+            .iter()
+            .map(|entry| {
+                let entry = entry?;
+                let (handle, _) = BlockHandle::decode(&entry.value)
+                    .ok_or_else(|| corrupted(format!("broken index in {:?}", file.path())))?;
+                Ok((entry.key, handle))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let filter = if footer.filter_handle.size > 0 {
+            let bytes = Self::read_block_contents(&file, footer.filter_handle)?;
+            BloomFilter::from_bytes(&bytes)
+        } else {
+            None
+        };
+        let compression = compression.resolve(footer.compression)?;
+        Ok(DataFile {
+            file,
+            index,
+            filter,
+            compression,
+        })
+    }
+
+    fn read_at(file: &RichFile, offset: Offset, buf: &mut [u8]) -> io::Result<()> {
+        file.read_exact_at(buf, offset)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => corrupted(format!(
+                    "{:?} is truncated at offset {}",
+                    file.path(),
+                    offset
+                )),
+                _ => err,
+            })
+    }
+
+    fn check_header(file: &RichFile) -> io::Result<()> {
+        let mut header = [0u8; Self::HEADER_SIZE as usize];
+        Self::read_at(file, 0, &mut header)?;
+        if header[0..4] != Self::MAGIC[..] {
+            return Err(corrupted(format!("{:?} is not a data file", file.path())));
+        }
+        if header[4] != Self::VERSION {
+            return Err(corrupted(format!(
+                "{:?} has unsupported version {}",
+                file.path(),
+                header[4]
+            )));
+        }
+        Ok(())
+    }
+
+    fn read_footer(file: &RichFile) -> io::Result<Footer> {
+        let len = file.underlying.metadata()?.len();
+        if len < Self::HEADER_SIZE + Self::TRAILER_SIZE {
+            return Err(corrupted(format!(
+                "{:?} is too short to be a data file",
+                file.path()
+            )));
+        }
+        let mut trailer = [0u8; Self::TRAILER_SIZE as usize];
+        Self::read_at(file, len - Self::TRAILER_SIZE, &mut trailer)?;
+        if trailer[12..16] != Self::MAGIC[..] {
+            return Err(corrupted(format!(
+                "{:?} does not end with footer",
+                file.path()
+            )));
+        }
+        let footer_offset = ByteUtils::as_u64(&trailer[0..8]);
+        let checksum = ByteUtils::as_usize(&trailer[8..12]) as u32;
+        let broken = || corrupted(format!("{:?} has broken footer", file.path()));
+        let footer_len = (len - Self::TRAILER_SIZE)
+            .checked_sub(footer_offset)
+            .ok_or_else(broken)?;
+        let mut footer = vec![0u8; footer_len as usize];
+        Self::read_at(file, footer_offset, &mut footer)?;
+        if crc32c::crc32c(&footer) != checksum {
+            return Err(corrupted(format!(
+                "checksum mismatch in footer of {:?}",
+                file.path()
+            )));
+        }
+
+        let mut pos = 0;
+        let mut take = |len: usize| {
+            let taken = footer.get(pos..(pos + len));
+            pos += len;
+            taken.ok_or_else(broken)
+        };
+        let entry_count = ByteUtils::as_u64(take(8)?);
+        let min_key_len = ByteUtils::as_usize(take(4)?);
+        let min_key = take(min_key_len)?.to_vec();
+        let max_key_len = ByteUtils::as_usize(take(4)?);
+        let max_key = take(max_key_len)?.to_vec();
+        let compression = take(1)?[0];
+        let handles = footer.get(pos..).ok_or_else(broken)?;
+        let (filter_handle, read) = BlockHandle::decode(handles).ok_or_else(broken)?;
+        let (index_handle, _) = BlockHandle::decode(&handles[read..]).ok_or_else(broken)?;
+        Ok(Footer {
+            entry_count,
+            min_key,
+            max_key,
+            compression,
+            filter_handle,
+            index_handle,
         })
     }
 
-    pub fn create<'a>(
-        &self,
-        memtable_entries: &'a MemtableEntries<String, String>,
-    ) -> io::Result<BTreeMap<&'a String, Offset>> {
-        let MemtableEntries {
-            entries,
-            tombstones, // TODO: persist records marked as deleted
-        } = memtable_entries;
+    fn read_block_contents(file: &RichFile, handle: BlockHandle) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0u8; handle.size as usize + 4];
+        Self::read_at(file, handle.offset, &mut bytes)?;
+        let checksum = ByteUtils::as_usize(&bytes[(handle.size as usize)..]) as u32;
+        bytes.truncate(handle.size as usize);
+        if crc32c::crc32c(&bytes) != checksum {
+            return Err(corrupted(format!(
+                "checksum mismatch in block at offset {} of {:?}",
+                handle.offset,
+                file.path()
+            )));
+        }
+        Ok(bytes)
+    }
+
+    fn read_block(file: &RichFile, handle: BlockHandle) -> io::Result<Block> {
+        Block::new(Self::read_block_contents(file, handle)?)
+    }
+
+    fn read_data_block(&self, handle: BlockHandle) -> io::Result<Block> {
+        let contents = Self::read_block_contents(&self.file, handle)?;
+        let decompressed = self.compression.decompress(&contents).map_err(|err| {
+            corrupted(format!(
+                "failed to decompress block at offset {} of {:?}: {}",
+                handle.offset,
+                self.file.path(),
+                err
+            ))
+        })?;
+        Block::new(decompressed)
+    }
+
+    fn to_data_entry(entry: BlockEntry) -> DataEntry {
+        match entry.kind {
+            EntryKind::Value => DataEntry::new(entry.key, entry.seq, Some(entry.value)),
+            EntryKind::Tombstone => DataEntry::new(entry.key, entry.seq, None),
+        }
+    }
+
+    pub fn filter(&self) -> Option<&BloomFilter> {
+        self.filter.as_ref()
+    }
+
+    /// Finds the newest version, including a tombstone, of `key` no newer than `seq`.
+    pub fn find(self: &Arc<Self>, key: &[u8], seq: SeqNo) -> io::Result<Lookup> {
+        // the first block whose last key is equal to or larger than `key`
+        let block_index = self
+            .index
+            .partition_point(|(last_key, _)| last_key.as_slice() < key);
+        let handle = match self.index.get(block_index) {
+            Some((_, handle)) => *handle,
+            None => return Ok(Lookup::Absent),
+        };
+        match self.read_data_block(handle)?.seek(key)? {
+            Some(entry) if entry.key == key && entry.seq <= seq => {
+                Ok(Lookup::Found(Self::to_data_entry(entry)))
+            }
+            Some(entry) if entry.key == key => {
+                // older versions follow in the same block or the next ones
+                let entry = self
+                    .cursor()
+                    .nearest(Bound::Included(key), Direction::Forward, seq)?;
+                Ok(match entry.filter(|entry| entry.key == key) {
+                    Some(entry) => Lookup::Found(entry),
+                    None => Lookup::Invisible,
+                })
+            }
+            _ => Ok(Lookup::Absent),
+        }
+    }
+
+    /// Iterates over all entries, including tombstones, in key order.
+    pub fn entries(self: &Arc<Self>) -> DataFileIter {
+        self.entries_from(Bound::Unbounded)
+    }
+
+    /// Same as `entries`, but skips the blocks and entries before `start`.
+    pub fn entries_from(self: &Arc<Self>, start: Bound<&[u8]>) -> DataFileIter {
+        let block_index = match start {
+            Bound::Included(key) | Bound::Excluded(key) => self
+                .index
+                .partition_point(|(last_key, _)| last_key.as_slice() < key),
+            Bound::Unbounded => 0,
+        };
+        DataFileIter {
+            data_file: self.clone(),
+            block_index,
+            entries: vec![].into_iter(),
+            start: start.map(<[u8]>::to_vec),
+        }
+    }
+
+    /// A raw cursor which caches the data block read last.
+    pub fn cursor(self: &Arc<Self>) -> DataFileCursor {
+        DataFileCursor {
+            data_file: self.clone(),
+            block: None,
+        }
+    }
 
-        let new_data_file = RichFile::open_file(&self.file.dir, "tmp_data", FileOption::New)?;
+    fn write_block(
+        writer: &mut impl Write,
+        contents: &[u8],
+        offset: &mut Offset,
+    ) -> io::Result<BlockHandle> {
+        writer.write_all(contents)?;
+        writer.write_all(&ByteUtils::from_usize(crc32c::crc32c(contents) as usize))?;
+        let handle = BlockHandle {
+            offset: *offset,
+            size: contents.len() as u64,
+        };
+        *offset += contents.len() as u64 + 4;
+        Ok(handle)
+    }
+
+    /// Writes `entries` sorted by key, and then from newest to oldest, into the data file of `data_gen`.
+    /// A tombstone is passed as an entry without value.
+    pub fn create(
+        dir_name: &str,
+        data_gen: DataGen,
+        entries: impl IntoIterator<Item = io::Result<DataEntry>>,
+        options: &DataFileOptions,
+    ) -> io::Result<Footer> {
+        let new_data_file =
+            RichFile::open_file(dir_name, Self::tmp_file_name(data_gen), FileOption::New)?;
         let mut data_writer = BufWriter::new(&new_data_file.underlying);
-        let mut offset: Offset = 0;
-
-        let mut new_index = BTreeMap::new();
-        entries.iter().for_each(|(key, value)| {
-            let key_bytes = key.as_bytes();
-            let value_bytes = value.as_bytes();
-            let size = 4 + 4 + key_bytes.len() + 4 + value_bytes.len();
-            let bytes: Vec<u8> =[
-              &ByteUtils::from_usize(size), 
-              &ByteUtils::from_usize(key_bytes.len()),
-              &ByteUtils::from_usize(value_bytes.len()),
-              key_bytes,
-              value_bytes,
-              b"\0"
-            ].concat();
-            if (size + 1) != bytes.len() {
-              panic!(format!("size is invalid. size: {}, bytes.len(): {}", size, bytes.len()));
+        data_writer.write_all(Self::MAGIC)?;
+        data_writer.write_all(&[Self::VERSION])?;
+        let mut offset: Offset = Self::HEADER_SIZE;
+
+        let mut data_block = BlockBuilder::new(options.block_restart_interval);
+        let mut index_block = BlockBuilder::new(1);
+        let mut add_index = |last_key: &[u8], handle: BlockHandle| {
+            let mut encoded = vec![];
+            handle.encode(&mut encoded);
+            index_block.add(last_key, 0, EntryKind::Value, &encoded);
+        };
+        // entries come in key order, and versions of a key come one after another
+        let mut min_key: Vec<u8> = vec![];
+        let mut max_key: Vec<u8> = vec![];
+        let mut entry_count: u64 = 0;
+        // hashes of distinct keys for the filter
+        let mut key_hashes: Vec<u32> = vec![];
+        for entry in entries {
+            let DataEntry {
+                key, seq, value, ..
+            } = entry?;
+            let kind = match value {
+                Some(_) => EntryKind::Value,
+                None => EntryKind::Tombstone,
+            };
+            data_block.add(&key, seq, kind, value.as_deref().unwrap_or_default());
+            if entry_count == 0 || key != max_key {
+                if options.bloom_bits_per_key.is_some() {
+                    key_hashes.push(BloomFilter::hash(&key));
+                }
+                if entry_count == 0 {
+                    min_key = key.clone();
+                }
+                max_key = key;
+            }
+            entry_count += 1;
+
+            if data_block.estimated_size() >= options.block_size {
+                let last_key = data_block.last_key().to_vec();
+                let contents = options.compression.compress(&data_block.finish())?;
+                let handle = Self::write_block(&mut data_writer, &contents, &mut offset)?;
+                add_index(&last_key, handle);
             }
-            data_writer.write(&bytes)
-                .expect("failed to to write bytes into BufWriter");
-            new_index.insert(key, offset);
-            offset += (size + 1) as u64;
-        });
-        data_writer.flush().expect("failed to write data");
-        std::fs::rename(new_data_file.path(), self.file.path())?;
-        return Ok(new_index);
+        }
+        if !data_block.is_empty() {
+            let last_key = data_block.last_key().to_vec();
+            let contents = options.compression.compress(&data_block.finish())?;
+            let handle = Self::write_block(&mut data_writer, &contents, &mut offset)?;
+            add_index(&last_key, handle);
+        }
+
+        let filter_handle = match options.bloom_bits_per_key {
+            Some(bits_per_key) => {
+                let filter = BloomFilter::build(&key_hashes, bits_per_key);
+                Self::write_block(&mut data_writer, &filter.to_bytes(), &mut offset)?
+            }
+            None => BlockHandle::default(),
+        };
+        let index_handle = Self::write_block(&mut data_writer, &index_block.finish(), &mut offset)?;
+
+        let mut footer: Vec<u8> = [
+            &ByteUtils::from_u64(entry_count)[..],
+            &ByteUtils::from_usize(min_key.len()),
+            &min_key,
+            &ByteUtils::from_usize(max_key.len()),
+            &max_key,
+            &[options.compression.id()],
+        ]
+        .concat();
+        filter_handle.encode(&mut footer);
+        index_handle.encode(&mut footer);
+        data_writer.write_all(&footer)?;
+        data_writer.write_all(&ByteUtils::from_u64(offset))?;
+        data_writer.write_all(&ByteUtils::from_usize(crc32c::crc32c(&footer) as usize))?;
+        data_writer.write_all(Self::MAGIC)?;
+        data_writer.flush()?;
+        drop(data_writer);
+        new_data_file.underlying.sync_all()?;
+        new_data_file.rename(&Self::path(dir_name, data_gen))?;
+        Ok(Footer {
+            entry_count,
+            min_key,
+            max_key,
+            compression: options.compression.id(),
+            filter_handle,
+            index_handle,
+        })
     }
 
     pub fn clear(dir: &str, data_gen: DataGen) -> io::Result<()> {
-        let tmp = Self::of(dir, data_gen);
-        std::fs::remove_file(tmp.file.path())?;
-        Ok(())
+        std::fs::remove_file(Self::path(dir, data_gen))
+    }
+}
+
+// iterators and cursors share the data file, so that it can be read
+// even after it is removed by a compaction
+pub(crate) struct DataFileIter {
+    data_file: Arc<DataFile>,
+    // the next block to be read
+    block_index: usize,
+    // entries of the current block
+    entries: std::vec::IntoIter<io::Result<DataEntry>>,
+    // entries before this are skipped until one past it is read, since
+    // versions of a key may span several blocks
+    start: Bound<Vec<u8>>,
+}
+impl Iterator for DataFileIter {
+    type Item = io::Result<DataEntry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(entry);
+            }
+            let (_, handle) = self.data_file.index.get(self.block_index)?;
+            self.block_index += 1;
+            let start = &self.start;
+            let entries: Vec<_> = match self.data_file.read_data_block(*handle) {
+                Ok(block) => block
+                    .iter()
+                    .map(|entry| entry.map(DataFile::to_data_entry))
+                    .filter(|entry| match (entry, start) {
+                        (Ok(entry), Bound::Included(start)) => &entry.key >= start,
+                        (Ok(entry), Bound::Excluded(start)) => &entry.key > start,
+                        _ => true,
+                    })
+                    .collect(),
+                Err(err) => {
+                    // no more entries can be read after broken block
+                    self.block_index = self.data_file.index.len();
+                    vec![Err(err)]
+                }
+            };
+            if !entries.is_empty() {
+                self.start = Bound::Unbounded;
+            }
+            self.entries = entries.into_iter();
+        }
+    }
+}
+
+pub(crate) struct DataFileCursor {
+    data_file: Arc<DataFile>,
+    // the block read last and its entries
+    block: Option<(usize, Vec<DataEntry>)>,
+}
+impl DataFileCursor {
+    fn block_entries(&mut self, block_index: usize) -> io::Result<&[DataEntry]> {
+        if !matches!(&self.block, Some((cached, _)) if *cached == block_index) {
+            let (_, handle) = self.data_file.index[block_index];
+            let entries = self
+                .data_file
+                .read_data_block(handle)?
+                .iter()
+                .map(|entry| entry.map(DataFile::to_data_entry))
+                .collect::<io::Result<_>>()?;
+            self.block = Some((block_index, entries));
+        }
+        Ok(self
+            .block
+            .as_ref()
+            .map(|(_, entries)| entries.as_slice())
+            .unwrap())
+    }
+}
+impl DataFileCursor {
+    // the first block which may hold an entry beyond `from`
+    fn block_index(&self, from: Bound<&[u8]>, direction: Direction) -> usize {
+        match from {
+            Bound::Included(key) | Bound::Excluded(key) => self
+                .data_file
+                .index
+                .partition_point(|(last_key, _)| last_key.as_slice() < key),
+            Bound::Unbounded if direction == Direction::Forward => 0,
+            Bound::Unbounded => self.data_file.index.len(),
+        }
+    }
+
+    // the first entry after `from` no newer than `seq`
+    fn first_visible(&mut self, from: Bound<&[u8]>, seq: SeqNo) -> io::Result<Option<DataEntry>> {
+        let mut block_index = self.block_index(from, Direction::Forward);
+        while block_index < self.data_file.index.len() {
+            let entries = self.block_entries(block_index)?;
+            // the number of entries before `from`
+            let pos = entries.partition_point(|entry| match from {
+                Bound::Included(key) => entry.key.as_slice() < key,
+                Bound::Excluded(key) => entry.key.as_slice() <= key,
+                Bound::Unbounded => false,
+            });
+            if let Some(entry) = entries[pos..].iter().find(|entry| entry.seq <= seq) {
+                return Ok(Some(entry.clone()));
+            }
+            block_index += 1;
+        }
+        Ok(None)
+    }
+
+    // the key of the last entry before `from` no newer than `seq`
+    fn last_visible_key(&mut self, from: Bound<&[u8]>, seq: SeqNo) -> io::Result<Option<Vec<u8>>> {
+        let block_count = self.data_file.index.len();
+        let mut block_index = match self.block_index(from, Direction::Backward) {
+            // every block is before `from`
+            index if index == block_count => match block_count.checked_sub(1) {
+                Some(last) => last,
+                None => return Ok(None),
+            },
+            index => index,
+        };
+        loop {
+            let entries = self.block_entries(block_index)?;
+            // the number of entries before `from`
+            let pos = entries.partition_point(|entry| match from {
+                Bound::Included(key) => entry.key.as_slice() <= key,
+                Bound::Excluded(key) => entry.key.as_slice() < key,
+                Bound::Unbounded => true,
+            });
+            if let Some(entry) = entries[..pos].iter().rev().find(|entry| entry.seq <= seq) {
+                return Ok(Some(entry.key.clone()));
+            }
+            block_index = match block_index.checked_sub(1) {
+                Some(prev) => prev,
+                None => return Ok(None),
+            };
+        }
+    }
+}
+impl RawCursor for DataFileCursor {
+    fn nearest(
+        &mut self,
+        from: Bound<&[u8]>,
+        direction: Direction,
+        seq: SeqNo,
+    ) -> io::Result<Option<DataEntry>> {
+        match direction {
+            Direction::Forward => self.first_visible(from, seq),
+            // the newest visible version of the key comes first among its versions
+            Direction::Backward => match self.last_visible_key(from, seq)? {
+                Some(key) => self.first_visible(Bound::Included(&key), seq),
+                None => Ok(None),
+            },
+        }
     }
 }
@@ -0,0 +1,138 @@
+use super::*;
+use std::{collections::BTreeMap, ops::Bound};
+
+#[derive(Clone, Debug)]
+pub(crate) struct FileMeta {
+    pub data_gen: DataGen,
+    pub level: usize,
+    // the newest flushed generation whose entries are merged into this one.
+    // level 0 is ordered by this.
+    pub newest_gen: DataGen,
+    pub size: u64,
+    pub entry_count: u64,
+    pub min_key: Vec<u8>,
+    pub max_key: Vec<u8>,
+}
+impl FileMeta {
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.min_key.as_slice() <= key && key <= self.max_key.as_slice()
+    }
+
+    pub fn overlaps(&self, min_key: &[u8], max_key: &[u8]) -> bool {
+        !(self.max_key.as_slice() < min_key || max_key < self.min_key.as_slice())
+    }
+
+    pub fn overlaps_range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> bool {
+        let after_start = match start {
+            Bound::Included(start) => start <= self.max_key.as_slice(),
+            Bound::Excluded(start) => start < self.max_key.as_slice(),
+            Bound::Unbounded => true,
+        };
+        let before_end = match end {
+            Bound::Included(end) => self.min_key.as_slice() <= end,
+            Bound::Excluded(end) => self.min_key.as_slice() < end,
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+}
+
+/*
+Levels keeps track of which level each data generation belongs to.
+- level 0 holds flushed generations whose key ranges may overlap each other.
+  A newer generation shadows older ones.
+- level N(>0) holds generations with non-overlapping key ranges.
+  Any generation in level N is older than the ones in level N-1.
+*/
+pub(crate) struct Levels {
+    files: BTreeMap<DataGen, FileMeta>,
+}
+
+impl Levels {
+    pub const MAX_LEVEL: usize = 6;
+
+    pub fn new(files: BTreeMap<DataGen, FileMeta>) -> Levels {
+        Levels { files }
+    }
+
+    pub fn add(&mut self, meta: FileMeta) {
+        self.files.insert(meta.data_gen, meta);
+    }
+
+    pub fn remove(&mut self, data_gen: DataGen) -> Option<FileMeta> {
+        self.files.remove(&data_gen)
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+
+    pub fn get(&self, data_gen: DataGen) -> Option<&FileMeta> {
+        self.files.get(&data_gen)
+    }
+
+    /// All generations in ascending order.
+    pub fn data_gens(&self) -> Vec<DataGen> {
+        self.files.keys().copied().collect()
+    }
+
+    /// Generations in `level`.
+    /// level 0 is sorted from oldest to newest, and the others are sorted by key.
+    pub fn level(&self, level: usize) -> Vec<&FileMeta> {
+        let mut files: Vec<_> = self.files.values().filter(|m| m.level == level).collect();
+        if level > 0 {
+            files.sort_by(|a, b| a.min_key.cmp(&b.min_key));
+        } else {
+            files.sort_by_key(|m| m.newest_gen);
+        }
+        files
+    }
+
+    pub fn level_size(&self, level: usize) -> u64 {
+        self.level(level).iter().map(|m| m.size).sum()
+    }
+
+    pub fn max_level(&self) -> usize {
+        self.files.values().map(|m| m.level).max().unwrap_or(0)
+    }
+
+    /// Generations which may contain `key`, from newest to oldest.
+    /// At most one generation is picked from each level other than level 0.
+    pub fn lookup_order(&self, key: &[u8]) -> Vec<DataGen> {
+        let mut gens: Vec<_> = self
+            .level(0)
+            .iter()
+            .rev()
+            .filter(|m| m.contains(key))
+            .map(|m| m.data_gen)
+            .collect();
+        (1..=self.max_level()).for_each(|level| {
+            if let Some(meta) = self.level(level).iter().find(|m| m.contains(key)) {
+                gens.push(meta.data_gen);
+            }
+        });
+        gens
+    }
+
+    /// Generations which may contain keys within the range, from newest to oldest.
+    pub fn scan_order(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Vec<DataGen> {
+        (0..=self.max_level())
+            .flat_map(|level| {
+                let mut files = self.level(level);
+                if level == 0 {
+                    files.reverse();
+                }
+                files
+            })
+            .filter(|m| m.overlaps_range(start, end))
+            .map(|m| m.data_gen)
+            .collect()
+    }
+
+    /// Whether any generation deeper than `level` overlaps with the key range.
+    pub fn overlaps_below(&self, level: usize, min_key: &[u8], max_key: &[u8]) -> bool {
+        self.files
+            .values()
+            .any(|m| m.level > level && m.overlaps(min_key, max_key))
+    }
+}
@@ -0,0 +1,275 @@
+use super::*;
+use crate::sst::byte_utils::*;
+use crate::sst::rich_file::*;
+use io::{Read, Write};
+use levels::FileMeta;
+use std::collections::BTreeMap;
+
+/// A change of generations on disk made by a flush or a compaction.
+#[derive(Default, Debug)]
+pub(crate) struct VersionEdit {
+    pub added: Vec<FileMeta>,
+    pub removed: Vec<DataGen>,
+    // the latest generation ever written
+    pub last_data_gen: Option<DataGen>,
+    // the latest sequence number ever persisted in data files
+    pub last_sequence: Option<SeqNo>,
+}
+
+/// Generations which form a consistent state, replayed from MANIFEST.
+#[derive(Default)]
+pub(crate) struct Version {
+    pub files: BTreeMap<DataGen, FileMeta>,
+    pub last_data_gen: DataGen,
+    pub last_sequence: SeqNo,
+    // whether replay stopped at a record torn at the tail
+    pub torn_tail: bool,
+}
+impl Version {
+    fn apply(&mut self, edit: VersionEdit) {
+        // a file moved into another level is removed and then added
+        edit.removed.iter().for_each(|gen| {
+            self.files.remove(gen);
+        });
+        edit.added.into_iter().for_each(|meta| {
+            self.files.insert(meta.data_gen, meta);
+        });
+        if let Some(last_data_gen) = edit.last_data_gen {
+            self.last_data_gen = self.last_data_gen.max(last_data_gen);
+        }
+        if let Some(last_sequence) = edit.last_sequence {
+            self.last_sequence = self.last_sequence.max(last_sequence);
+        }
+    }
+}
+
+enum Record<'a> {
+    Valid(&'a [u8]),
+    // checksum mismatch
+    Broken,
+    // the header or the payload runs past the end of MANIFEST
+    PastEof,
+}
+
+/*
+MANIFEST is an append-only log of version edits.
+A data file becomes live only after the edit adding it is appended,
+and is removed from disk only after the edit removing it is appended.
+
+MANIFEST layout:
+[payload length][checksum][payload]...
+<---4 byte-----><-4 byte-><------->
+
+- checksum is CRC32C of the payload.
+- payload is a sequence of the fields below.
+  - last data gen: [tag = 1][data gen]
+                   <-1 byte-><4 byte->
+  - added file:    [tag = 2][data gen][level][newest gen][data size][entry count][min key len][min key][max key len][max key]
+                   <-1 byte-><4 byte-><4byte><--4 byte--><-8 byte--><--8 byte---><--4 byte---><-------><--4 byte---><------->
+  - removed file:  [tag = 3][data gen]
+                   <-1 byte-><4 byte->
+  - last sequence: [tag = 4][sequence number]
+                   <-1 byte-><----8 byte----->
+*/
+pub(crate) struct Manifest {
+    dir_name: String,
+    file: RichFile,
+    // set once an append fails, after which MANIFEST may end with a partial record
+    failed: bool,
+}
+
+impl Manifest {
+    const FILE_NAME: &'static str = "MANIFEST";
+    const TAG_LAST_DATA_GEN: u8 = 1;
+    const TAG_ADDED: u8 = 2;
+    const TAG_REMOVED: u8 = 3;
+    const TAG_LAST_SEQUENCE: u8 = 4;
+
+    /// Replays MANIFEST, and then rewrites it into a single edit of the replayed version.
+    /// A record torn at the tail is regarded as never written, and any other broken record fails.
+    pub fn open(dir_name: &str) -> io::Result<(Manifest, Version)> {
+        let version = match std::fs::File::open(Self::path(dir_name)) {
+            Ok(mut file) => {
+                let mut bytes = vec![];
+                file.read_to_end(&mut bytes)?;
+                Self::replay(&bytes)?
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Version::default(),
+            Err(err) => return Err(err),
+        };
+        let manifest = Self::write_snapshot(dir_name, &version)?;
+        Ok((manifest, version))
+    }
+
+    fn replay(bytes: &[u8]) -> io::Result<Version> {
+        let mut version = Version::default();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            match Self::read_record(bytes, pos) {
+                Record::Valid(payload) => {
+                    version.apply(Self::decode(payload)?);
+                    pos += 8 + payload.len();
+                }
+                // a partially written record is possible only as the last one appended,
+                // so a broken length followed by more records is corruption
+                Record::PastEof if !Self::any_record_after(bytes, pos) => {
+                    log::warn!(
+                        "ignored {} bytes torn at the tail of MANIFEST",
+                        bytes.len() - pos
+                    );
+                    version.torn_tail = true;
+                    break;
+                }
+                _ => return Err(corrupted(format!("MANIFEST is broken at offset {}", pos))),
+            }
+        }
+        Ok(version)
+    }
+
+    fn read_record(bytes: &[u8], pos: usize) -> Record<'_> {
+        let header = match bytes.get(pos..(pos + 8)) {
+            Some(header) => header,
+            None => return Record::PastEof,
+        };
+        let len = ByteUtils::as_usize(&header[0..4]);
+        let checksum = ByteUtils::as_usize(&header[4..8]) as u32;
+        match bytes.get((pos + 8)..(pos + 8 + len)) {
+            Some(payload) if crc32c::crc32c(payload) == checksum => Record::Valid(payload),
+            Some(_) => Record::Broken,
+            None => Record::PastEof,
+        }
+    }
+
+    // whether a valid record starts anywhere after `pos`
+    fn any_record_after(bytes: &[u8], pos: usize) -> bool {
+        ((pos + 1)..bytes.len()).any(|pos| {
+            matches!(Self::read_record(bytes, pos), Record::Valid(payload) if !payload.is_empty())
+        })
+    }
+
+    fn decode(payload: &[u8]) -> io::Result<VersionEdit> {
+        let mut edit = VersionEdit::default();
+        let mut pos = 0;
+        let broken = || corrupted("MANIFEST has a broken version edit");
+        let mut take = |len: usize| {
+            let taken = payload.get(pos..(pos + len));
+            pos += len;
+            taken.ok_or_else(broken)
+        };
+        while let Ok(tag) = take(1) {
+            match tag[0] {
+                Self::TAG_LAST_DATA_GEN => {
+                    edit.last_data_gen = Some(ByteUtils::as_usize(take(4)?) as DataGen);
+                }
+                Self::TAG_ADDED => {
+                    let data_gen = ByteUtils::as_usize(take(4)?) as DataGen;
+                    let level = ByteUtils::as_usize(take(4)?);
+                    let newest_gen = ByteUtils::as_usize(take(4)?) as DataGen;
+                    let size = ByteUtils::as_u64(take(8)?);
+                    let entry_count = ByteUtils::as_u64(take(8)?);
+                    let min_key_len = ByteUtils::as_usize(take(4)?);
+                    let min_key = take(min_key_len)?.to_vec();
+                    let max_key_len = ByteUtils::as_usize(take(4)?);
+                    let max_key = take(max_key_len)?.to_vec();
+                    edit.added.push(FileMeta {
+                        data_gen,
+                        level,
+                        newest_gen,
+                        size,
+                        entry_count,
+                        min_key,
+                        max_key,
+                    });
+                }
+                Self::TAG_REMOVED => edit.removed.push(ByteUtils::as_usize(take(4)?) as DataGen),
+                Self::TAG_LAST_SEQUENCE => edit.last_sequence = Some(ByteUtils::as_u64(take(8)?)),
+                tag => {
+                    return Err(corrupted(format!(
+                        "MANIFEST has unknown tag {} in a version edit",
+                        tag
+                    )))
+                }
+            }
+        }
+        Ok(edit)
+    }
+
+    fn encode(edit: &VersionEdit) -> Vec<u8> {
+        let mut payload = vec![];
+        if let Some(last_data_gen) = edit.last_data_gen {
+            payload.push(Self::TAG_LAST_DATA_GEN);
+            payload.extend_from_slice(&ByteUtils::from_usize(last_data_gen as usize));
+        }
+        if let Some(last_sequence) = edit.last_sequence {
+            payload.push(Self::TAG_LAST_SEQUENCE);
+            payload.extend_from_slice(&ByteUtils::from_u64(last_sequence));
+        }
+        edit.removed.iter().for_each(|gen| {
+            payload.push(Self::TAG_REMOVED);
+            payload.extend_from_slice(&ByteUtils::from_usize(*gen as usize));
+        });
+        edit.added.iter().for_each(|meta| {
+            payload.push(Self::TAG_ADDED);
+            payload.extend_from_slice(&ByteUtils::from_usize(meta.data_gen as usize));
+            payload.extend_from_slice(&ByteUtils::from_usize(meta.level));
+            payload.extend_from_slice(&ByteUtils::from_usize(meta.newest_gen as usize));
+            payload.extend_from_slice(&ByteUtils::from_u64(meta.size));
+            payload.extend_from_slice(&ByteUtils::from_u64(meta.entry_count));
+            payload.extend_from_slice(&ByteUtils::from_usize(meta.min_key.len()));
+            payload.extend_from_slice(&meta.min_key);
+            payload.extend_from_slice(&ByteUtils::from_usize(meta.max_key.len()));
+            payload.extend_from_slice(&meta.max_key);
+        });
+        let checksum = crc32c::crc32c(&payload) as usize;
+        [
+            &ByteUtils::from_usize(payload.len())[..],
+            &ByteUtils::from_usize(checksum),
+            &payload,
+        ]
+        .concat()
+    }
+
+    /// Appends `edit`, and makes it durable before returning.
+    pub fn append(&mut self, edit: &VersionEdit) -> io::Result<()> {
+        if self.failed {
+            // an edit appended after a partial one would be taken as corruption at open
+            return Err(io::Error::other(
+                "MANIFEST is not writable after a failed append",
+            ));
+        }
+        let mut file = &self.file.underlying;
+        let result = file
+            .write_all(&Self::encode(edit))
+            .and_then(|_| file.sync_data());
+        self.failed = result.is_err();
+        result
+    }
+
+    /// Replaces MANIFEST with the one holding only `version`.
+    pub fn reset(&mut self, version: &Version) -> io::Result<()> {
+        *self = Self::write_snapshot(&self.dir_name, version)?;
+        Ok(())
+    }
+
+    fn write_snapshot(dir_name: &str, version: &Version) -> io::Result<Manifest> {
+        let tmp_file = RichFile::open_file(dir_name, "tmp_manifest", FileOption::New)?;
+        let edit = VersionEdit {
+            added: version.files.values().cloned().collect(),
+            removed: vec![],
+            last_data_gen: Some(version.last_data_gen),
+            last_sequence: Some(version.last_sequence),
+        };
+        (&tmp_file.underlying).write_all(&Self::encode(&edit))?;
+        tmp_file.underlying.sync_all()?;
+        tmp_file.rename(&Self::path(dir_name))?;
+        Ok(Manifest {
+            dir_name: dir_name.to_string(),
+            file: RichFile::open_file(dir_name, Self::FILE_NAME, FileOption::Append)?,
+            failed: false,
+        })
+    }
+
+    fn path(dir_name: &str) -> std::path::PathBuf {
+        std::path::Path::new(dir_name).join(Self::FILE_NAME)
+    }
+}
@@ -0,0 +1,214 @@
+//! Iterators over entries of `SSTable` in key order.
+
+use super::{codec::Codec, disktable::DataEntry, memtable::MemtableEntries, snapshot::SeqNo};
+use std::{io, marker::PhantomData, ops::Bound, sync::Arc};
+
+pub(crate) type EntryIter = Box<dyn Iterator<Item = io::Result<DataEntry>> + Send>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Forward,
+    Backward,
+}
+
+/// A source of entries, including tombstones, which can be looked up in either direction.
+pub(crate) trait RawCursor {
+    /// The newest version not newer than `seq` of the nearest key beyond `from` in `direction`,
+    /// e.g. the first key larger than `key` for `Bound::Excluded(key)` and `Direction::Forward`.
+    /// Keys without such a version are skipped.
+    fn nearest(
+        &mut self,
+        from: Bound<&[u8]>,
+        direction: Direction,
+        seq: SeqNo,
+    ) -> io::Result<Option<DataEntry>>;
+}
+
+impl RawCursor for Arc<MemtableEntries<Vec<u8>, Vec<u8>>> {
+    fn nearest(
+        &mut self,
+        from: Bound<&[u8]>,
+        direction: Direction,
+        seq: SeqNo,
+    ) -> io::Result<Option<DataEntry>> {
+        let from = from.map(<[u8]>::to_vec);
+        let entry = MemtableEntries::nearest(self, from.as_ref(), direction, seq);
+        Ok(entry.map(|(key, seq, value)| DataEntry::new(key.clone(), seq, value.cloned())))
+    }
+}
+
+/// An iterator over entries within a range, returned by `SSTable::range`.
+/// Entries of the memtable and every data generation are merged, where newer ones
+/// shadow older ones and deleted keys are skipped.
+/// It holds no lock on `SSTable`, and sees the entries at the time it was created.
+pub struct Range<K, V> {
+    // merged versions including tombstones, from the start of the range
+    merged: EntryIter,
+    end: Bound<Vec<u8>>,
+    // versions newer than this are invisible
+    seq: SeqNo,
+    // the key whose visible version is already yielded or skipped
+    last_key: Option<Vec<u8>>,
+    done: bool,
+    codec: PhantomData<(K, V)>,
+}
+
+impl<K: Codec, V: Codec> Range<K, V> {
+    pub(crate) fn new(merged: EntryIter, end: Bound<Vec<u8>>, seq: SeqNo) -> Range<K, V> {
+        Range {
+            merged,
+            end,
+            seq,
+            last_key: None,
+            done: false,
+            codec: PhantomData,
+        }
+    }
+
+    pub(crate) fn empty() -> Range<K, V> {
+        Range {
+            merged: Box::new(std::iter::empty()),
+            end: Bound::Unbounded,
+            seq: 0,
+            last_key: None,
+            done: true,
+            codec: PhantomData,
+        }
+    }
+
+    fn before_end(&self, key: &[u8]) -> bool {
+        match &self.end {
+            Bound::Included(end) => key <= end.as_slice(),
+            Bound::Excluded(end) => key < end.as_slice(),
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+impl<K: Codec, V: Codec> Iterator for Range<K, V> {
+    type Item = io::Result<(K, V)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let entry = match self.merged.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(err)) => {
+                    // no more entries can be merged in order after an error
+                    self.done = true;
+                    return Some(Err(err));
+                }
+                None => break,
+            };
+            if !self.before_end(&entry.key) {
+                break;
+            }
+            // only the newest visible version of a key counts
+            if entry.seq > self.seq || self.last_key.as_ref() == Some(&entry.key) {
+                continue;
+            }
+            self.last_key = Some(entry.key.clone());
+            if let Some(value) = entry.value {
+                return Some(K::decode(&entry.key).and_then(|key| Ok((key, V::decode(&value)?))));
+            }
+        }
+        self.done = true;
+        None
+    }
+}
+
+/// A cursor over live entries, which moves in both directions, returned by `SSTable::cursor`.
+/// Newer entries shadow older ones and deleted keys are skipped, as `SSTable::get` does.
+/// A cursor is not positioned until one of `seek`s is called.
+pub struct Cursor<K, V> {
+    // raw cursors from newest to oldest
+    sources: Vec<Box<dyn RawCursor + Send>>,
+    // versions newer than this are invisible
+    seq: SeqNo,
+    // the encoded key and the decoded entry where the cursor is
+    current: Option<(Vec<u8>, (K, V))>,
+}
+
+impl<K: Codec, V: Codec> Cursor<K, V> {
+    pub(crate) fn new(sources: Vec<Box<dyn RawCursor + Send>>, seq: SeqNo) -> Cursor<K, V> {
+        Cursor {
+            sources,
+            seq,
+            current: None,
+        }
+    }
+
+    /// Moves to the first entry whose key is equal to or larger than `key`.
+    pub fn seek(&mut self, key: &K) -> io::Result<()> {
+        self.move_to(Bound::Included(&key.encode()), Direction::Forward)
+    }
+
+    pub fn seek_to_first(&mut self) -> io::Result<()> {
+        self.move_to(Bound::Unbounded, Direction::Forward)
+    }
+
+    pub fn seek_to_last(&mut self) -> io::Result<()> {
+        self.move_to(Bound::Unbounded, Direction::Backward)
+    }
+
+    /// Moves to the next entry. Does nothing if the cursor is not positioned.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> io::Result<()> {
+        self.step(Direction::Forward)
+    }
+
+    /// Moves to the previous entry. Does nothing if the cursor is not positioned.
+    pub fn prev(&mut self) -> io::Result<()> {
+        self.step(Direction::Backward)
+    }
+
+    /// The entry where the cursor is, or `None` if it has moved past either end.
+    pub fn entry(&self) -> Option<(&K, &V)> {
+        self.current.as_ref().map(|(_, (key, value))| (key, value))
+    }
+
+    fn step(&mut self, direction: Direction) -> io::Result<()> {
+        match self.current.take() {
+            Some((key, _)) => self.move_to(Bound::Excluded(&key), direction),
+            None => Ok(()),
+        }
+    }
+
+    fn move_to(&mut self, from: Bound<&[u8]>, direction: Direction) -> io::Result<()> {
+        self.current = None;
+        let mut from = from.map(<[u8]>::to_vec);
+        loop {
+            let mut nearest: Option<DataEntry> = None;
+            for source in self.sources.iter_mut() {
+                let entry =
+                    match source.nearest(from.as_ref().map(Vec::as_slice), direction, self.seq)? {
+                        Some(entry) => entry,
+                        None => continue,
+                    };
+                // the newest source wins among the same keys
+                let closer = match &nearest {
+                    Some(nearest) if direction == Direction::Forward => entry.key < nearest.key,
+                    Some(nearest) => entry.key > nearest.key,
+                    None => true,
+                };
+                if closer {
+                    nearest = Some(entry);
+                }
+            }
+            match nearest {
+                Some(DataEntry {
+                    key,
+                    value: Some(value),
+                    ..
+                }) => {
+                    let entry = (K::decode(&key)?, V::decode(&value)?);
+                    self.current = Some((key, entry));
+                    return Ok(());
+                }
+                // a deleted key is skipped
+                Some(DataEntry {
+                    key, value: None, ..
+                }) => from = Bound::Excluded(key),
+                None => return Ok(()),
+            }
+        }
+    }
+}
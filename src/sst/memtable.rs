@@ -1,189 +1,325 @@
 mod wal;
-use log;
-use std::{
-    collections::{BTreeMap, BTreeSet},
-    hash::Hash,
-    io,
+use super::{
+    iter::Direction,
+    snapshot::{SeqNo, SnapshotList},
 };
+use im::OrdMap;
+use log;
+use std::{cmp::Reverse, hash::Hash, io, ops::Bound, sync::Arc};
+pub use wal::{DroppedRecord, WalRecoveryReport};
+pub(crate) use wal::{SealedSegments, WriteAheadLog};
+
+/// A version of a key with its sequence number, where a tombstone has `None` as its value.
+pub(crate) type MemtableVersion<'a, K, V> = (&'a K, SeqNo, Option<&'a V>);
 
-pub(crate) trait Memtable {
+// a key of `MemtableEntries`, which sorts versions of a key from newest to oldest
+type VersionKey<K> = (K, Reverse<SeqNo>);
+
+// WAL is written by the caller, so that a write doesn't hold the memtable during I/O
+pub(crate) trait Memtable: Send + Sync {
     type Key;
     type Value;
-    fn get(&self, key: &Self::Key) -> GetResult<&Self::Value>;
-    fn set(
-        &mut self,
-        key: Self::Key,
-        value: Self::Value,
-    ) -> MemtableOnFlush<Self::Key, Self::Value>;
-    fn delete(&mut self, key: Self::Key) -> ();
-    fn clear(&mut self) -> ();
-    fn restore(&mut self) -> io::Result<()>;
-}
-pub(crate) enum GetResult<T> {
-    Found(T),
-    Deleted,
-    NotFound,
+    /// Applies `ops`, where the i-th op takes `first_seq + i`.
+    /// A delete is an op without value.
+    fn write(&mut self, ops: Vec<(Self::Key, Option<Self::Value>)>, first_seq: SeqNo);
+    /// Whether the memtable has grown enough to be flushed.
+    fn is_full(&self) -> bool;
+    /// Hands over the entries with `sealed`, WAL segments of them, and starts over empty.
+    fn flush(&mut self, sealed: SealedSegments) -> ImmutableMemtable<Self::Key, Self::Value>;
+    /// A frozen view of every version, which later writes don't change.
+    fn entries(&self) -> Arc<MemtableEntries<Self::Key, Self::Value>>;
+    /// The largest sequence number written, or 0 if empty.
+    fn last_sequence(&self) -> SeqNo;
+    fn clear(&mut self);
 }
-pub(crate) struct MemtableOnFlush<Key, Value> {
-    flushed: Option<MemtableEntries<Key, Value>>,
+/// A memtable which no longer takes writes, waiting to be flushed into disktable.
+pub(crate) struct ImmutableMemtable<Key, Value> {
+    pub entries: Arc<MemtableEntries<Key, Value>>,
+    // WAL of the entries, which is needed until they are persisted
+    sealed: SealedSegments,
 }
+#[derive(Clone)]
 pub(crate) struct MemtableEntries<Key, Value> {
-    pub entries: Box<BTreeMap<Key, Value>>,
-    pub tombstones: Box<BTreeSet<Key>>,
+    // versions of a key are sorted from newest to oldest. a tombstone has no value.
+    // a persistent map, so that a write copies only the nodes it changes
+    // while readers share the older entries.
+    pub entries: OrdMap<VersionKey<Key>, Option<Value>>,
 }
 
-impl<K: Hash + Eq + Ord + From<String>, V: From<String>> MemtableEntries<K, V> {
-    pub fn get(&self, key: &K) -> GetResult<&V> {
-        if self.tombstones.get(key).is_none() {
-            self.entries
-                .get(key)
-                .map(GetResult::Found)
-                .unwrap_or(GetResult::NotFound)
-        } else {
-            GetResult::Deleted
+impl<K: Ord + Clone, V: Clone> MemtableEntries<K, V> {
+    pub fn new() -> MemtableEntries<K, V> {
+        MemtableEntries {
+            entries: OrdMap::new(),
+        }
+    }
+
+    /// The newest version of `key`, including a tombstone, which is not newer than `seq`.
+    pub fn find(&self, key: &K, seq: SeqNo) -> Option<MemtableVersion<'_, K, V>> {
+        self.range(Bound::Included(key), Bound::Included(key))
+            .find(|(_, s, _)| *s <= seq)
+    }
+
+    /// Iterates over every version, including tombstones, in key order.
+    /// A tombstone is yielded with `None` as its value.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = MemtableVersion<'_, K, V>> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Same as `iter`, but only within the range.
+    pub fn range(
+        &self,
+        start: Bound<&K>,
+        end: Bound<&K>,
+    ) -> impl DoubleEndedIterator<Item = MemtableVersion<'_, K, V>> {
+        self.entries
+            .range(Self::version_bounds(start, end))
+            .map(|((key, Reverse(seq)), value)| (key, *seq, value.as_ref()))
+    }
+
+    // bounds of versions of keys within the range
+    fn version_bounds(
+        start: Bound<&K>,
+        end: Bound<&K>,
+    ) -> (Bound<VersionKey<K>>, Bound<VersionKey<K>>) {
+        // the newest version of a key comes first, and the oldest one last
+        let first = |key: &K| (key.clone(), Reverse(SeqNo::MAX));
+        let last = |key: &K| (key.clone(), Reverse(0));
+        let start = match start {
+            Bound::Included(key) => Bound::Included(first(key)),
+            Bound::Excluded(key) => Bound::Excluded(last(key)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match end {
+            Bound::Included(key) => Bound::Included(last(key)),
+            Bound::Excluded(key) => Bound::Excluded(first(key)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        (start, end)
+    }
+
+    /// Same as `range`, but the iterator shares the entries instead of borrowing them.
+    pub fn shared_range(self: &Arc<Self>, start: Bound<&K>, end: Bound<&K>) -> SharedRange<K, V> {
+        let (next, end) = Self::version_bounds(start, end);
+        SharedRange {
+            entries: self.clone(),
+            next,
+            end,
         }
     }
-}
 
-impl<Key, Value> MemtableOnFlush<Key, Value> {
-    pub fn on_flush(
-        self,
-        f: impl FnOnce(MemtableEntries<Key, Value>) -> io::Result<()>,
-    ) -> io::Result<()> {
-        match self.flushed {
-            Some(flushed) => f(flushed),
-            None => Ok(()),
+    /// The nearest version not newer than `seq`, including a tombstone, beyond `from` in `direction`.
+    pub fn nearest(
+        &self,
+        from: Bound<&K>,
+        direction: Direction,
+        seq: SeqNo,
+    ) -> Option<MemtableVersion<'_, K, V>> {
+        let visible = |(_, s, _): &MemtableVersion<K, V>| *s <= seq;
+        match direction {
+            Direction::Forward => self.range(from, Bound::Unbounded).find(visible),
+            Direction::Backward => {
+                // the newest visible version of the key comes first in forward order
+                let (key, _, _) = self.range(Bound::Unbounded, from).rev().find(visible)?;
+                self.range(Bound::Included(key), Bound::Included(key))
+                    .find(visible)
+            }
         }
     }
+
+    /// Adds a version, and then drops older versions of the key which no reader can see,
+    /// i.e. the ones shadowed by a newer version visible to `oldest_snapshot`.
+    /// Returns the values of dropped versions, where a tombstone has `None`.
+    fn add_version(
+        &mut self,
+        key: K,
+        seq: SeqNo,
+        value: Option<V>,
+        oldest_snapshot: SeqNo,
+    ) -> Vec<Option<V>> {
+        self.entries.insert((key.clone(), Reverse(seq)), value);
+        let mut newer_visible = false;
+        let obsolete: Vec<SeqNo> = self
+            .range(Bound::Included(&key), Bound::Included(&key))
+            .filter_map(|(_, s, _)| {
+                let obsolete = newer_visible;
+                newer_visible |= s <= oldest_snapshot;
+                Some(s).filter(|_| obsolete)
+            })
+            .collect();
+        obsolete
+            .into_iter()
+            .filter_map(|s| self.entries.remove(&(key.clone(), Reverse(s))))
+            .collect()
+    }
+}
+
+/// An iterator over versions within a range of `MemtableEntries` shared by `Arc`,
+/// which can outlive the memtable.
+pub(crate) struct SharedRange<K, V> {
+    entries: Arc<MemtableEntries<K, V>>,
+    // where the next version is looked up from
+    next: Bound<VersionKey<K>>,
+    end: Bound<VersionKey<K>>,
+}
+impl<K: Ord + Clone, V: Clone> Iterator for SharedRange<K, V> {
+    type Item = (K, SeqNo, Option<V>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let ((key, seq), value) = self
+            .entries
+            .entries
+            .range((self.next.clone(), self.end.clone()))
+            .next()?;
+        self.next = Bound::Excluded((key.clone(), *seq));
+        Some((key.clone(), seq.0, value.clone()))
+    }
+}
+
+impl<Key, Value> ImmutableMemtable<Key, Value> {
+    /// Archives WAL of the entries, which is no longer needed once they are persisted.
+    pub fn persisted(self) -> io::Result<()> {
+        self.sealed.archive()
+    }
+
+    /// Removes WAL of the entries, which are dropped without being persisted.
+    pub fn discard(self) -> io::Result<()> {
+        self.sealed.remove()
+    }
 }
 
 pub(crate) mod default {
     use super::*;
-    use std::{
-        collections::{BTreeMap, BTreeSet},
-        hash::Hash,
+    use crate::sst::{
+        options::{SyncPolicy, WalRecoveryMode, WalRetention},
+        snapshot::LATEST,
     };
-    use wal::WriteAheadLog;
 
     pub struct BTreeMemtable<K, V> {
-        max_entry: usize,
-        underlying: BTreeMap<K, V>,
-        tombstone: BTreeSet<K>,
-        wal: WriteAheadLog,
+        write_buffer_size: usize,
+        max_entry: Option<usize>,
+        // shared with readers, and copied on write while they hold it.
+        // the copy shares the nodes of the persistent map, so it costs O(1).
+        underlying: Arc<MemtableEntries<K, V>>,
+        // approximate bytes taken by every version
+        size: usize,
+        // number of versions, including tombstones
+        version_count: usize,
+        snapshots: Arc<SnapshotList>,
     }
-    impl<K: Hash + Eq + Ord + From<String>, V: From<String>> BTreeMemtable<K, V> {
-        pub fn new(dir_name: &str, max_entry: usize) -> BTreeMemtable<K, V> {
-            let (underlying, tombstone) = Self::restore_from_wal(dir_name);
-            let wal = WriteAheadLog::create(dir_name);
-            BTreeMemtable {
+    impl<
+            K: Hash + Eq + Ord + Clone + AsRef<[u8]> + From<Vec<u8>> + Send + Sync,
+            V: Clone + AsRef<[u8]> + From<Vec<u8>> + Send + Sync,
+        > BTreeMemtable<K, V>
+    {
+        /// Replays WAL into a new memtable, and returns it with WAL to write next.
+        pub fn recover(
+            dir_name: &str,
+            write_buffer_size: usize,
+            max_entry: Option<usize>,
+            sync_policy: SyncPolicy,
+            wal_retention: WalRetention,
+            wal_recovery_mode: WalRecoveryMode,
+            snapshots: Arc<SnapshotList>,
+        ) -> io::Result<(BTreeMemtable<K, V>, WriteAheadLog, WalRecoveryReport)> {
+            let (entries, report) = WriteAheadLog::recover(dir_name, wal_recovery_mode)?;
+            let mut wal = WriteAheadLog::create(dir_name, sync_policy, wal_retention)?;
+            let mut memtable = BTreeMemtable::<K, V> {
+                write_buffer_size,
                 max_entry,
-                wal,
-                underlying,
-                tombstone,
-            }
-        }
-
-        fn restore_from_wal(dir_name: &str) -> (BTreeMap<K, V>, BTreeSet<K>) {
-            let mut underlying = BTreeMap::new();
-            let mut tombstone = BTreeSet::new();
-            match WriteAheadLog::restore(dir_name) {
-                Some(restored) => restored.for_each(|entry| match entry {
-                    Ok(wal::Entry::Inserted { key, value }) => {
-                        underlying.insert(From::from(key), From::from(value));
-                    }
-                    Ok(wal::Entry::Deleted { key }) => {
-                        tombstone.insert(From::from(key));
-                    }
-                    Err(message) => {
-                        panic!("failed to restore a line. {}", message);
-                    }
-                }),
-                None => (),
+                underlying: Arc::new(MemtableEntries::new()),
+                size: 0,
+                version_count: 0,
+                snapshots,
             };
-            (underlying, tombstone)
+            memtable.restore(entries);
+            if !report.dropped.is_empty() {
+                // not to drop the same records again, the recovered entries are moved
+                // into the new segment and the broken segments are archived
+                for (key, seq, value) in memtable.underlying.iter() {
+                    match value {
+                        Some(value) => wal.insert((key.as_ref(), value.as_ref()), seq)?,
+                        None => wal.delete(key.as_ref(), seq)?,
+                    }
+                }
+                wal.archive_live_segments()?;
+            }
+            Ok((memtable, wal, report))
         }
 
-        fn is_deleted(&self, key: &K) -> bool {
-            self.tombstone.get(key).is_some()
+        fn restore(&mut self, entries: Vec<wal::Entry>) {
+            // no snapshot can be taken before recovery
+            entries.into_iter().for_each(|entry| match entry {
+                wal::Entry::Inserted { key, seq, value } => {
+                    self.add_version(From::from(key), seq, Some(From::from(value)), LATEST)
+                }
+                wal::Entry::Deleted { key, seq } => {
+                    self.add_version(From::from(key), seq, None, LATEST)
+                }
+            });
         }
 
-        fn with_check_tombstone<T>(
-            &self,
-            key: &K,
-            deleted: impl Fn() -> T,
-            not_deleted: impl Fn() -> T,
-        ) -> T {
-            if self.is_deleted(key) {
-                deleted()
-            } else {
-                not_deleted()
-            }
+        // a key, a value if any, and a sequence number
+        fn version_size(key: &K, value: Option<&V>) -> usize {
+            key.as_ref().len() + value.map_or(0, |value| value.as_ref().len()) + 8
         }
 
-        fn flush(&mut self) -> MemtableEntries<K, V> {
-            let contents = std::mem::replace(&mut self.underlying, BTreeMap::new());
-            let deleted = std::mem::replace(&mut self.tombstone, BTreeSet::new());
-            self.wal.clear().expect("failed to clear WAL");
-            MemtableEntries {
-                entries: Box::new(contents),
-                tombstones: Box::new(deleted),
+        fn add_version(&mut self, key: K, seq: SeqNo, value: Option<V>, oldest_snapshot: SeqNo) {
+            self.size += Self::version_size(&key, value.as_ref());
+            self.version_count += 1;
+            let dropped = Arc::make_mut(&mut self.underlying).add_version(
+                key.clone(),
+                seq,
+                value,
+                oldest_snapshot,
+            );
+            for value in dropped {
+                self.size -= Self::version_size(&key, value.as_ref());
+                self.version_count -= 1;
             }
         }
     }
 
-    impl<K: Hash + Eq + Ord + ToString + From<String>, V: ToString + From<String>> Memtable
-        for BTreeMemtable<K, V>
+    impl<
+            K: Hash + Eq + Ord + Clone + AsRef<[u8]> + From<Vec<u8>> + Send + Sync,
+            V: Clone + AsRef<[u8]> + From<Vec<u8>> + Send + Sync,
+        > Memtable for BTreeMemtable<K, V>
     {
         type Key = K;
         type Value = V;
 
-        fn get(&self, key: &Self::Key) -> GetResult<&Self::Value> {
-            // log::trace!("why memtable#find was called!");
-            self.with_check_tombstone(
-                key,
-                || GetResult::Deleted,
-                || {
-                    self.underlying
-                        .get(&key)
-                        .map(GetResult::Found)
-                        .unwrap_or(GetResult::NotFound)
-                },
-            )
+        fn write(&mut self, ops: Vec<(Self::Key, Option<Self::Value>)>, first_seq: SeqNo) {
+            let oldest_snapshot = self.snapshots.oldest();
+            (first_seq..).zip(ops).for_each(|(seq, (key, value))| {
+                self.add_version(key, seq, value, oldest_snapshot);
+            });
         }
-
-        fn set(
-            &mut self,
-            key: Self::Key,
-            value: Self::Value,
-        ) -> MemtableOnFlush<Self::Key, Self::Value> {
-            self.tombstone.remove(&key);
-            self.wal
-                .insert((&key.to_string(), &value.to_string()))
-                .expect("failed to write WAL");
-            self.underlying.insert(key, value);
-            if self.underlying.len() > self.max_entry {
-                log::trace!("flush!");
-                MemtableOnFlush {
-                    flushed: Some(self.flush()),
-                }
-            } else {
-                MemtableOnFlush { flushed: None }
-            }
+        fn is_full(&self) -> bool {
+            self.size > self.write_buffer_size
+                || self
+                    .max_entry
+                    .is_some_and(|max_entry| self.version_count > max_entry)
+        }
+        fn flush(&mut self, sealed: SealedSegments) -> ImmutableMemtable<Self::Key, Self::Value> {
+            log::trace!("flush!");
+            let entries = std::mem::replace(&mut self.underlying, Arc::new(MemtableEntries::new()));
+            self.size = 0;
+            self.version_count = 0;
+            ImmutableMemtable { entries, sealed }
         }
-        fn delete(&mut self, key: Self::Key) -> () {
-            self.wal
-                .delete(&key.to_string())
-                .expect("failed to write WAL");
-            self.underlying.remove(&key);
-            self.tombstone.insert(key);
+        fn entries(&self) -> Arc<MemtableEntries<Self::Key, Self::Value>> {
+            self.underlying.clone()
         }
-        fn clear(&mut self) -> () {
-            self.wal.clear().expect("failed to clear WAL");
-            self.underlying.clear();
-            self.tombstone.clear();
+        fn last_sequence(&self) -> SeqNo {
+            self.underlying
+                .entries
+                .keys()
+                .map(|(_, Reverse(seq))| *seq)
+                .max()
+                .unwrap_or(0)
         }
-        fn restore(&mut self) -> io::Result<()> {
-            todo!()
+        fn clear(&mut self) {
+            self.underlying = Arc::new(MemtableEntries::new());
+            self.size = 0;
+            self.version_count = 0;
         }
     }
 }
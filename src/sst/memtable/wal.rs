@@ -1,102 +1,535 @@
-use crate::sst::rich_file::*;
-use io::{BufRead, BufReader, Lines, Write};
+use crate::sst::{
+    byte_utils::*,
+    disktable::corrupted,
+    options::{SyncPolicy, WalRecoveryMode, WalRetention},
+    rich_file::*,
+    snapshot::SeqNo,
+};
+use io::Write;
 use std::{
     fs::File,
     io::{self, BufWriter},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
 };
 
+type SegmentNumber = u64;
+
 pub(crate) enum Entry {
-    Inserted { key: String, value: String },
-    Deleted { key: String },
+    Inserted {
+        key: Vec<u8>,
+        seq: SeqNo,
+        value: Vec<u8>,
+    },
+    Deleted {
+        key: Vec<u8>,
+        seq: SeqNo,
+    },
 }
 
+/*
+WAL record layout:
+[length][length checksum][checksum][record type][sequence][key length][value length][key][value]
+<4 byte><----4 byte-----><-4 byte-><--1 byte---><-varint-><-varint---><--varint----><---><----->
+
+- length is the number of bytes following checksum.
+- length checksum is CRC32C of length, so that a broken length is told
+  from a record torn by a crash without looking into the following bytes.
+- checksum is CRC32C of the rest of the record.
+- sequence is the sequence number of the write.
+- value length and value are written only for an inserted entry.
+
+A write batch is written as one record, so that it is replayed all or nothing:
+[length][length checksum][checksum][record type = 3][first sequence][count][op]...
+<4 byte><----4 byte-----><-4 byte-><----1 byte-----><---varint-----><varint>
+
+- op is [record type][key length][value length][key][value] of an entry,
+  whose sequence number is the first sequence plus its index in the batch.
+
+WAL is split into numbered segments, `wal_<number>.log`, and a new one starts per memtable.
+Segments of a flushed memtable are moved into `wal_archive` directory,
+where they are kept according to `WalRetention`.
+*/
 pub(crate) struct WriteAheadLog {
     dir_name: String,
     writer: BufWriter<File>,
+    // the segment being written
+    segment: SegmentNumber,
+    // older segments whose entries are not flushed yet
+    live_segments: Vec<SegmentNumber>,
+    retention: WalRetention,
+    sync_policy: SyncPolicy,
+    // number of writes since the last sync
+    unsynced: usize,
+    last_synced_at: Instant,
+    // set once a write or a sync fails, after which the segment may end with a partial record
+    failed: bool,
 }
-pub(crate) struct WalRestore {
-    buf: Lines<BufReader<File>>,
+/// What happened while replaying WAL at open.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WalRecoveryReport {
+    /// number of records replayed into memtable
+    pub recovered_records: usize,
+    /// records which were not replayed
+    pub dropped: Vec<DroppedRecord>,
 }
-impl Iterator for WalRestore {
-    type Item = Result<Entry, String>;
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.buf.next() {
-            Some(Ok(line)) => Some(WriteAheadLog::parse_line(&line)),
-            _ => None,
-        }
-    }
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DroppedRecord {
+    pub segment: PathBuf,
+    pub offset: u64,
+    /// number of bytes dropped from `offset`
+    pub bytes: u64,
+    pub reason: String,
+}
+
+enum RecordError {
+    // a record whose tail was not written yet, which is possible only at the tail of a segment
+    Torn,
+    // `len` is the length of the record if it can be told
+    Corrupted { reason: String, len: Option<usize> },
 }
 
 impl WriteAheadLog {
-    const FILE_NAME: &'static str = "wal.log";
-    const DELIMITER: &'static str = "\0";
-    const TAG_DELETED: &'static str = "D";
-    const TAG_INSERTED: &'static str = "I";
+    const FILE_NAME_PREFIX: &'static str = "wal_";
+    const FILE_NAME_SUFFIX: &'static str = ".log";
+    const ARCHIVE_DIR: &'static str = "wal_archive";
+    const TYPE_INSERTED: u8 = 1;
+    const TYPE_DELETED: u8 = 2;
+    const TYPE_BATCH: u8 = 3;
+    // [length][length checksum][checksum]
+    const HEADER_LEN: usize = 12;
 
-    pub fn create(dir_name: &str) -> WriteAheadLog {
-        WriteAheadLog {
+    /// Starts a new segment following the existing ones, which stay live.
+    pub fn create(
+        dir_name: &str,
+        sync_policy: SyncPolicy,
+        retention: WalRetention,
+    ) -> io::Result<WriteAheadLog> {
+        let live_segments = Self::segments(Path::new(dir_name))?;
+        let archived = Self::segments(&Path::new(dir_name).join(Self::ARCHIVE_DIR))?;
+        let segment = live_segments
+            .iter()
+            .chain(archived.iter())
+            .max()
+            .map_or(1, |n| n + 1);
+        Ok(WriteAheadLog {
             dir_name: dir_name.into(),
-            writer: Self::writer(dir_name),
+            writer: Self::writer(dir_name, segment)?,
+            segment,
+            live_segments,
+            retention,
+            sync_policy,
+            unsynced: 0,
+            last_synced_at: Instant::now(),
+            failed: false,
+        })
+    }
+    fn writer(dir_name: &str, segment: SegmentNumber) -> io::Result<BufWriter<File>> {
+        let file = RichFile::open_file(dir_name, Self::file_name(segment), FileOption::New)?;
+        Ok(BufWriter::new(file.underlying))
+    }
+    fn file_name(segment: SegmentNumber) -> String {
+        format!(
+            "{}{:06}{}",
+            Self::FILE_NAME_PREFIX,
+            segment,
+            Self::FILE_NAME_SUFFIX
+        )
+    }
+
+    // numbers of segments in `dir` in ascending order
+    fn segments(dir: &Path) -> io::Result<Vec<SegmentNumber>> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let mut segments = vec![];
+        for entry in entries {
+            let file_name = entry?.file_name();
+            let segment = file_name
+                .to_string_lossy()
+                .strip_prefix(Self::FILE_NAME_PREFIX)
+                .and_then(|name| name.strip_suffix(Self::FILE_NAME_SUFFIX))
+                .and_then(|number| number.parse::<SegmentNumber>().ok());
+            segments.extend(segment);
         }
+        segments.sort_unstable();
+        Ok(segments)
     }
-    fn writer(dir_name: &str) -> BufWriter<File> {
-        let file = Self::open_file(dir_name, FileOption::New).expect("failed to open WAL file");
-        BufWriter::new(file.underlying)
+
+    // appends [key length][value length][key][value] of an entry
+    fn encode_entry(buf: &mut Vec<u8>, key: &[u8], value: Option<&[u8]>) {
+        ByteUtils::put_varint(buf, key.len() as u64);
+        if let Some(value) = value {
+            ByteUtils::put_varint(buf, value.len() as u64);
+        }
+        buf.extend_from_slice(key);
+        if let Some(value) = value {
+            buf.extend_from_slice(value);
+        }
     }
-    fn open_file(dir_name: &str, option: FileOption) -> io::Result<RichFile> {
-        RichFile::open_file(dir_name, Self::FILE_NAME, option)
+
+    fn encode(record_type: u8, seq: SeqNo, key: &[u8], value: Option<&[u8]>) -> Vec<u8> {
+        let mut record = vec![record_type];
+        ByteUtils::put_varint(&mut record, seq);
+        Self::encode_entry(&mut record, key, value);
+        Self::with_header(record)
+    }
+
+    fn encode_batch(first_seq: SeqNo, ops: &[(&[u8], Option<&[u8]>)]) -> Vec<u8> {
+        let mut record = vec![Self::TYPE_BATCH];
+        ByteUtils::put_varint(&mut record, first_seq);
+        ByteUtils::put_varint(&mut record, ops.len() as u64);
+        for (key, value) in ops {
+            record.push(match value {
+                Some(_) => Self::TYPE_INSERTED,
+                None => Self::TYPE_DELETED,
+            });
+            Self::encode_entry(&mut record, key, *value);
+        }
+        Self::with_header(record)
+    }
+
+    fn with_header(record: Vec<u8>) -> Vec<u8> {
+        let len = ByteUtils::from_usize(record.len());
+        let len_checksum = ByteUtils::from_usize(crc32c::crc32c(&len) as usize);
+        let checksum = ByteUtils::from_usize(crc32c::crc32c(&record) as usize);
+        [&len[..], &len_checksum, &checksum, &record].concat()
+    }
+
+    fn varint(bytes: &[u8], pos: &mut usize) -> Option<usize> {
+        let (n, read) = ByteUtils::get_varint(bytes.get(*pos..)?)?;
+        *pos += read;
+        Some(n as usize)
+    }
+
+    // decodes [key length][value length][key][value] at `pos` of a whole record
+    fn decode_entry(
+        record: &[u8],
+        record_type: u8,
+        seq: SeqNo,
+        pos: &mut usize,
+    ) -> Result<Entry, String> {
+        let truncated = || "truncated entry".to_string();
+        let key_len = Self::varint(record, pos).ok_or_else(truncated)?;
+        let value_len = match record_type {
+            Self::TYPE_INSERTED => Some(Self::varint(record, pos).ok_or_else(truncated)?),
+            _ => None,
+        };
+        let mut take = |len: usize| {
+            let bytes = record
+                .get(*pos..pos.saturating_add(len))
+                .ok_or_else(truncated)?;
+            *pos += len;
+            Ok::<_, String>(bytes.to_vec())
+        };
+        let key = take(key_len)?;
+        match (record_type, value_len) {
+            (Self::TYPE_INSERTED, Some(value_len)) => Ok(Entry::Inserted {
+                key,
+                seq,
+                value: take(value_len)?,
+            }),
+            (Self::TYPE_DELETED, _) => Ok(Entry::Deleted { key, seq }),
+            (record_type, _) => Err(format!("unknown record type({})", record_type)),
+        }
+    }
+
+    // decodes a record at the head of `bytes`, and returns its entries with the number of bytes read.
+    fn decode(bytes: &[u8]) -> Result<(Vec<Entry>, usize), RecordError> {
+        // the header of the last record may be left partially written
+        let header = bytes.get(..Self::HEADER_LEN).ok_or(RecordError::Torn)?;
+        let len_checksum = ByteUtils::as_usize(&header[4..8]) as u32;
+        if crc32c::crc32c(&header[0..4]) != len_checksum {
+            return Err(RecordError::Corrupted {
+                reason: "length checksum mismatch".to_string(),
+                len: None,
+            });
+        }
+        // the length is intact, so a record running past the end is really cut short
+        let end = Self::HEADER_LEN.saturating_add(ByteUtils::as_usize(&header[0..4]));
+        if end > bytes.len() {
+            return Err(RecordError::Torn);
+        }
+        let corrupted = |reason: String| RecordError::Corrupted {
+            reason,
+            len: Some(end),
+        };
+        let checksum = ByteUtils::as_usize(&header[8..12]) as u32;
+        let record = &bytes[Self::HEADER_LEN..end];
+        if crc32c::crc32c(record) != checksum {
+            // the tail of the last record may be left unwritten
+            if end == bytes.len() {
+                return Err(RecordError::Torn);
+            }
+            return Err(corrupted("checksum mismatch".to_string()));
+        }
+        Self::decode_record(record)
+            .map(|entries| (entries, end))
+            .map_err(corrupted)
+    }
+
+    // decodes the entries of a whole record following its header
+    fn decode_record(record: &[u8]) -> Result<Vec<Entry>, String> {
+        let record_type = *record.first().ok_or_else(|| "empty record".to_string())?;
+        let mut pos = 1;
+        let entries = match record_type {
+            Self::TYPE_BATCH => {
+                let first_seq = Self::varint(record, &mut pos);
+                let count = Self::varint(record, &mut pos);
+                match (first_seq, count) {
+                    (Some(first_seq), Some(count)) => (0..count)
+                        .map(|i| {
+                            let op_type = *record
+                                .get(pos)
+                                .ok_or_else(|| "truncated entry".to_string())?;
+                            pos += 1;
+                            let seq = (first_seq + i) as SeqNo;
+                            Self::decode_entry(record, op_type, seq, &mut pos)
+                        })
+                        .collect::<Result<_, _>>()?,
+                    _ => return Err("truncated batch header".to_string()),
+                }
+            }
+            _ => {
+                let seq = Self::varint(record, &mut pos)
+                    .ok_or_else(|| "truncated entry".to_string())?
+                    as SeqNo;
+                vec![Self::decode_entry(record, record_type, seq, &mut pos)?]
+            }
+        };
+        if pos != record.len() {
+            return Err("trailing bytes in a record".to_string());
+        }
+        Ok(entries)
     }
 
-    pub fn insert(&mut self, entry: (&str, &str)) -> io::Result<()> {
+    pub fn insert(&mut self, entry: (&[u8], &[u8]), seq: SeqNo) -> io::Result<()> {
         let (key, value) = entry;
-        let str = format!(
-            "{}{}{}{}{}\n",
-            Self::TAG_INSERTED,
-            Self::DELIMITER,
-            key,
-            Self::DELIMITER,
-            value
-        );
-        self.writer.write(str.as_bytes()).unwrap();
-        self.writer.flush()
+        self.write(&Self::encode(Self::TYPE_INSERTED, seq, key, Some(value)))
+    }
+
+    pub fn delete(&mut self, key: &[u8], seq: SeqNo) -> io::Result<()> {
+        self.write(&Self::encode(Self::TYPE_DELETED, seq, key, None))
+    }
+
+    /// Writes `ops` as one record, where the i-th op takes `first_seq + i`.
+    /// A delete is an op without value.
+    pub fn write_batch(
+        &mut self,
+        first_seq: SeqNo,
+        ops: &[(&[u8], Option<&[u8]>)],
+    ) -> io::Result<()> {
+        self.write(&Self::encode_batch(first_seq, ops))
+    }
+
+    fn write(&mut self, record: &[u8]) -> io::Result<()> {
+        if self.failed {
+            // a record appended after a partial one would be taken as corruption at recovery
+            return Err(io::Error::other("WAL is not writable after a failed write"));
+        }
+        let result = self.append(record);
+        self.failed = result.is_err();
+        result
     }
 
-    pub fn delete(&mut self, key: &str) -> io::Result<()> {
-        let str = format!("{}{}{}\n", Self::TAG_DELETED, Self::DELIMITER, key);
-        self.writer.write(str.as_bytes()).unwrap();
-        self.writer.flush()
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        self.writer.write_all(record)?;
+        self.writer.flush()?;
+        self.unsynced += 1;
+        let needs_sync = match self.sync_policy {
+            SyncPolicy::None => false,
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::EveryN(n) => self.unsynced >= n,
+            SyncPolicy::Interval(interval) => self.last_synced_at.elapsed() >= interval,
+        };
+        if needs_sync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Makes all records written so far durable.
+    pub fn sync(&mut self) -> io::Result<()> {
+        let synced = self
+            .writer
+            .flush()
+            .and_then(|_| self.writer.get_ref().sync_data());
+        if synced.is_err() {
+            // records not made durable can't be told from the ones to be written next
+            self.failed = true;
+            return synced;
+        }
+        self.unsynced = 0;
+        self.last_synced_at = Instant::now();
+        Ok(())
     }
 
+    /// Syncs records written since the last sync, if any.
+    pub fn sync_pending(&mut self) -> io::Result<()> {
+        if self.unsynced == 0 {
+            return Ok(());
+        }
+        self.sync()
+    }
+
+    /// Starts a new segment, and returns the older ones
+    /// to be archived once their entries are flushed.
+    pub fn rotate(&mut self) -> io::Result<SealedSegments> {
+        self.sync()?;
+        // nothing changes unless the new segment is opened
+        let writer = Self::writer(&self.dir_name, self.segment + 1)?;
+        let mut sealed = std::mem::take(&mut self.live_segments);
+        sealed.push(self.segment);
+        self.segment += 1;
+        self.writer = writer;
+        Ok(SealedSegments {
+            dir_name: self.dir_name.clone(),
+            segments: sealed,
+            retention: self.retention,
+        })
+    }
+
+    /// Removes all live segments, and starts a new one.
     pub fn clear(&mut self) -> io::Result<()> {
-        self.writer = Self::writer(&self.dir_name);
+        let dir = Path::new(&self.dir_name);
+        for segment in self.live_segments.iter().chain([self.segment].iter()) {
+            std::fs::remove_file(dir.join(Self::file_name(*segment)))?;
+        }
+        let writer = Self::writer(&self.dir_name, self.segment + 1)?;
+        self.live_segments.clear();
+        self.segment += 1;
+        self.writer = writer;
+        self.unsynced = 0;
         Ok(())
     }
 
-    pub fn restore(dir_name: &str) -> Option<WalRestore> {
-        match Self::open_file(dir_name, FileOption::ReadOnly) {
-            Ok(file) => {
-                let buf = BufReader::new(file.underlying).lines();
-                Some(WalRestore { buf })
+    /// Reads entries of live segments from oldest to newest according to `mode`.
+    pub fn recover(
+        dir_name: &str,
+        mode: WalRecoveryMode,
+    ) -> io::Result<(Vec<Entry>, WalRecoveryReport)> {
+        let dir = Path::new(dir_name);
+        let mut entries = vec![];
+        let mut report = WalRecoveryReport::default();
+        let mut stopped = false;
+        for segment in Self::segments(dir)? {
+            let path = dir.join(Self::file_name(segment));
+            let bytes = std::fs::read(&path)?;
+            let mut pos = 0;
+            while pos < bytes.len() {
+                let mut drop = |len: usize, reason: String| {
+                    log::warn!(
+                        "dropped {} bytes at offset {} of {:?} in WAL: {}",
+                        len,
+                        pos,
+                        path,
+                        reason
+                    );
+                    report.dropped.push(DroppedRecord {
+                        segment: path.clone(),
+                        offset: pos as u64,
+                        bytes: len as u64,
+                        reason,
+                    });
+                };
+                if stopped {
+                    drop(bytes.len(), "follows a corrupted record".to_string());
+                    break;
+                }
+                let (reason, len, torn) = match Self::decode(&bytes[pos..]) {
+                    Ok((record, len)) => {
+                        entries.extend(record);
+                        report.recovered_records += 1;
+                        pos += len;
+                        continue;
+                    }
+                    Err(RecordError::Torn) => ("torn record".to_string(), None, true),
+                    Err(RecordError::Corrupted { reason, len }) => (reason, len, false),
+                };
+                match mode {
+                    // a torn record is left only at the tail of a segment by a crash
+                    _ if torn && mode != WalRecoveryMode::AbsoluteConsistency => {}
+                    WalRecoveryMode::AbsoluteConsistency
+                    | WalRecoveryMode::TolerateCorruptedTailRecords => {
+                        return Err(corrupted(format!(
+                            "{} at offset {} of {:?} in WAL",
+                            reason, pos, path
+                        )))
+                    }
+                    WalRecoveryMode::PointInTime => stopped = true,
+                    WalRecoveryMode::SkipAnyCorruptedRecords => {
+                        if let Some(len) = len {
+                            drop(len, reason);
+                            pos += len;
+                            continue;
+                        }
+                    }
+                }
+                drop(bytes.len() - pos, reason);
+                break;
             }
-            _ => None,
         }
+        Ok((entries, report))
     }
-    fn parse_line(line: &str) -> Result<Entry, String> {
-        let res: Vec<_> = line.split(Self::DELIMITER).collect();
-        match res[0] {
-            Self::TAG_INSERTED => {
-                let key = res[1].to_string();
-                let value = res[2].to_string();
-                Ok(Entry::Inserted { key, value })
-            }
-            Self::TAG_DELETED => {
-                let key = res[1].to_string();
-                Ok(Entry::Deleted { key })
-            }
-            _ => Err(format!(
-                "unknown tag({}) was written in line({:?})",
-                res[0], res
-            )),
+
+    /// Archives live segments, whose entries must have been written into the current one.
+    pub fn archive_live_segments(&mut self) -> io::Result<()> {
+        self.sync()?;
+        SealedSegments {
+            dir_name: self.dir_name.clone(),
+            segments: std::mem::take(&mut self.live_segments),
+            retention: self.retention,
         }
+        .archive()
+    }
+}
+
+/// Segments whose entries are being flushed.
+pub(crate) struct SealedSegments {
+    dir_name: String,
+    segments: Vec<SegmentNumber>,
+    retention: WalRetention,
+}
+impl SealedSegments {
+    pub fn remove(self) -> io::Result<()> {
+        let dir = Path::new(&self.dir_name);
+        for segment in &self.segments {
+            std::fs::remove_file(dir.join(WriteAheadLog::file_name(*segment)))?;
+        }
+        Ok(())
+    }
+
+    /// Moves segments into archive directory, and then removes old archived segments.
+    pub fn archive(self) -> io::Result<()> {
+        let dir = Path::new(&self.dir_name);
+        let archive_dir = dir.join(WriteAheadLog::ARCHIVE_DIR);
+        std::fs::create_dir_all(&archive_dir)?;
+        for segment in &self.segments {
+            let file_name = WriteAheadLog::file_name(*segment);
+            std::fs::rename(dir.join(&file_name), archive_dir.join(&file_name))?;
+        }
+
+        let archived = WriteAheadLog::segments(&archive_dir)?;
+        let expired: Vec<_> = match self.retention {
+            WalRetention::Count(count) => archived[..archived.len().saturating_sub(count)].to_vec(),
+            WalRetention::Age(age) => archived
+                .into_iter()
+                .filter(|segment| {
+                    let path = archive_dir.join(WriteAheadLog::file_name(*segment));
+                    std::fs::metadata(path)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                        .is_some_and(|elapsed| elapsed > age)
+                })
+                .collect(),
+        };
+        for segment in expired {
+            std::fs::remove_file(archive_dir.join(WriteAheadLog::file_name(segment)))?;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,123 @@
+//! Tuning knobs of SSTable.
+
+use super::compression::Compression;
+use std::time::Duration;
+
+pub struct Options {
+    /// memtable is flushed into disktable when it takes more bytes than this,
+    /// counting keys, values and tombstones
+    pub write_buffer_size: usize,
+    /// memtable is flushed as well when it holds more entries than this, tombstones included. `None` disables the limit.
+    pub mem_max_entry: Option<usize>,
+    /// memtables which are full are flushed by a background thread.
+    /// writes wait for it while more memtables than this are waiting to be flushed.
+    pub max_immutable_memtables: usize,
+    /// compaction runs automatically once the number of data generations
+    /// in level 0 reaches this threshold. `None` disables automatic compaction.
+    pub compaction_threshold: Option<usize>,
+    pub compaction_strategy: CompactionStrategy,
+    /// bits of Bloom filter per key built for each data generation.
+    /// `None` disables Bloom filters.
+    pub bloom_bits_per_key: Option<usize>,
+    /// approximate size of a data block, which is the unit of reading a data file
+    pub block_size: usize,
+    /// number of keys between restart points, where a key is stored without prefix compression
+    pub block_restart_interval: usize,
+    /// codec to compress data blocks of newly written data files.
+    /// data files written with another codec stay readable.
+    pub compression: Compression,
+    /// when WAL is synced to disk
+    pub sync_policy: SyncPolicy,
+    /// how long WAL segments of flushed memtables are kept in `wal_archive` directory
+    pub wal_retention: WalRetention,
+    /// how to treat broken records in WAL at open
+    pub wal_recovery_mode: WalRecoveryMode,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            write_buffer_size: 4 * 1024 * 1024,
+            mem_max_entry: None,
+            max_immutable_memtables: 2,
+            compaction_threshold: Some(4),
+            compaction_strategy: CompactionStrategy::SizeTiered,
+            bloom_bits_per_key: Some(10),
+            block_size: 4 * 1024,
+            block_restart_interval: 16,
+            compression: Compression::Snappy,
+            sync_policy: SyncPolicy::None,
+            wal_retention: WalRetention::Count(4),
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
+        }
+    }
+}
+
+/// Durability of writes.
+/// A write which is not synced yet can be lost by a crash of OS, but not by a crash of the process.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPolicy {
+    /// WAL is synced only by `SSTable::sync`
+    None,
+    /// WAL is synced on every write
+    EveryWrite,
+    /// WAL is synced once per this number of writes
+    EveryN(usize),
+    /// WAL is synced by a background thread once per this duration,
+    /// and on a write when this duration has passed since the last sync
+    Interval(Duration),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WalRetention {
+    /// keeps this number of the newest archived segments
+    Count(usize),
+    /// keeps archived segments modified within this duration
+    Age(Duration),
+}
+
+/// How to treat broken records in WAL at open.
+/// Unless recovery fails, segments holding broken records are archived
+/// after the recovered entries are written into a new segment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WalRecoveryMode {
+    /// fails on any broken record
+    AbsoluteConsistency,
+    /// drops an incomplete record at the tail of a segment, which a crash can leave,
+    /// and fails on any other broken record
+    TolerateCorruptedTailRecords,
+    /// stops recovery at the first corrupted record, and drops all records after it
+    PointInTime,
+    /// drops corrupted records, and recovers the others
+    SkipAnyCorruptedRecords,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum CompactionStrategy {
+    /// merges adjacent generations of similar size.
+    /// every generation stays in level 0.
+    SizeTiered,
+    /// moves generations from level 0 into deeper levels,
+    /// each of which holds generations with non-overlapping key ranges.
+    Leveled(LeveledOptions),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LeveledOptions {
+    /// total size of data files in level 1
+    pub max_bytes_for_level_base: u64,
+    /// level N+1 can be this times larger than level N
+    pub max_bytes_for_level_multiplier: u64,
+    /// size of a data file written by compaction
+    pub target_file_size: u64,
+}
+
+impl Default for LeveledOptions {
+    fn default() -> Self {
+        LeveledOptions {
+            max_bytes_for_level_base: 10 * 1024 * 1024,
+            max_bytes_for_level_multiplier: 10,
+            target_file_size: 2 * 1024 * 1024,
+        }
+    }
+}
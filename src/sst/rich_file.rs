@@ -1,7 +1,6 @@
 use std::{
     fs::{File, OpenOptions},
     io,
-    ops::Deref,
     path::{Path, PathBuf},
 };
 pub(crate) struct RichFile {
@@ -14,22 +13,13 @@ pub(crate) struct RichFile {
 pub(crate) enum FileOption {
     New,
     Append,
-    ReadOnly,
 }
 impl FileOption {
     fn open(&self, path: &PathBuf) -> Result<File, io::Error> {
         let mut option = OpenOptions::new();
         match self {
             FileOption::New => option.read(true).write(true).truncate(true).create(true),
-            FileOption::Append => option.read(true).append(true).truncate(false).create(true),
-            FileOption::ReadOnly => {
-                OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .truncate(false)
-                    .open(path)?;
-                option.read(true)
-            }
+            FileOption::Append => option.read(true).append(true).create(true),
         }
         .open(path)
     }
@@ -44,9 +34,15 @@ impl RichFile {
         let dir = Path::new(&dir_name);
         let file_name_s: String = file_name.into();
         let path = dir.join(&file_name_s);
-        let file = option
-            .open(&path)
-            .expect(format!("failed to open file({:?}), option: {:?}", &path, option).deref());
+        let file = option.open(&path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "failed to open file({:?}), option: {:?}, error: {}",
+                    &path, option, err
+                ),
+            )
+        })?;
 
         Ok(RichFile {
             underlying: file,
@@ -57,4 +53,50 @@ impl RichFile {
     pub fn path(&self) -> PathBuf {
         Path::new(&self.dir).join(&self.name)
     }
+
+    /// Renames the file to `path`, and then syncs the directory of `path`,
+    /// so that the rename is not lost by a crash.
+    pub fn rename(&self, path: &Path) -> io::Result<()> {
+        std::fs::rename(self.path(), path)?;
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        Self::sync_dir(dir)
+    }
+
+    #[cfg(unix)]
+    fn sync_dir(dir: &Path) -> io::Result<()> {
+        File::open(dir)?.sync_all()
+    }
+
+    // a directory can't be opened as a file on Windows, where NTFS journals renames
+    #[cfg(windows)]
+    fn sync_dir(_dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Reads exactly `buf.len()` bytes at `offset` without moving the cursor of the file,
+    /// so that threads can read the same file in parallel.
+    #[cfg(unix)]
+    pub fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(&self.underlying, buf, offset)
+    }
+
+    #[cfg(windows)]
+    pub fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let mut read = 0;
+        while read < buf.len() {
+            let n = std::os::windows::fs::FileExt::seek_read(
+                &self.underlying,
+                &mut buf[read..],
+                offset + read as u64,
+            )?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            read += n;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,111 @@
+//! Point-in-time views of `SSTable`.
+
+use super::{codec::Codec, Cursor, Range, SSTable};
+use std::{
+    collections::BTreeMap,
+    io,
+    ops::RangeBounds,
+    sync::{Arc, Mutex},
+};
+
+/// Sequence number tagged on every write, which increases monotonically.
+pub(crate) type SeqNo = u64;
+
+/// The sequence number to see all writes so far.
+pub(crate) const LATEST: SeqNo = SeqNo::MAX;
+
+/// Sequence numbers of live snapshots.
+/// Versions of a key visible to any of them are kept by flushes and compactions.
+#[derive(Default)]
+pub(crate) struct SnapshotList {
+    // sequence number -> number of snapshots taken at it
+    live: Mutex<BTreeMap<SeqNo, usize>>,
+}
+
+impl SnapshotList {
+    fn acquire(&self, seq: SeqNo) {
+        *self.live.lock().unwrap().entry(seq).or_default() += 1;
+    }
+
+    fn release(&self, seq: SeqNo) {
+        let mut live = self.live.lock().unwrap();
+        if let Some(count) = live.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                live.remove(&seq);
+            }
+        }
+    }
+
+    /// The oldest sequence number any reader may see.
+    pub fn oldest(&self) -> SeqNo {
+        self.live
+            .lock()
+            .unwrap()
+            .keys()
+            .next()
+            .copied()
+            .unwrap_or(LATEST)
+    }
+}
+
+/// A frozen view of `SSTable` at the time `SSTable::snapshot` was called.
+/// Writes after that, including flushes and compactions, are invisible through it.
+/// Versions needed by a snapshot are kept on disk until it is dropped.
+pub struct Snapshot {
+    seq: SeqNo,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(seq: SeqNo, list: &Arc<SnapshotList>) -> Snapshot {
+        list.acquire(seq);
+        Snapshot {
+            seq,
+            list: list.clone(),
+        }
+    }
+
+    /// The sequence number of the last write visible through this snapshot.
+    pub fn sequence(&self) -> u64 {
+        self.seq
+    }
+
+    pub(crate) fn check<K, V>(&self, sst: &SSTable<K, V>) -> io::Result<()> {
+        if Arc::ptr_eq(&self.list, &sst.snapshots) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "snapshot was taken from another SSTable",
+            ))
+        }
+    }
+
+    pub fn get<K: Codec, V: Codec>(&self, sst: &SSTable<K, V>, key: &K) -> io::Result<Option<V>> {
+        self.check(sst)?;
+        sst.get_at(key, self.seq)
+    }
+
+    /// Same as `SSTable::range`, but at this snapshot.
+    pub fn range<K: Codec, V: Codec>(
+        &self,
+        sst: &SSTable<K, V>,
+        range: impl RangeBounds<K>,
+    ) -> io::Result<Range<K, V>> {
+        self.check(sst)?;
+        Ok(sst.range_at(range, self.seq))
+    }
+
+    /// Same as `SSTable::cursor`, but at this snapshot.
+    pub fn cursor<K: Codec, V: Codec>(&self, sst: &SSTable<K, V>) -> io::Result<Cursor<K, V>> {
+        self.check(sst)?;
+        Ok(sst.cursor_at(self.seq))
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list.release(self.seq);
+    }
+}
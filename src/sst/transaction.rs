@@ -0,0 +1,92 @@
+//! Optimistic transactions over `SSTable`.
+
+use super::{codec::Codec, SSTable, Snapshot, WriteBatch};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fmt, io,
+    marker::PhantomData,
+};
+
+/// A transaction returned by `SSTable::begin_transaction`.
+/// Reads see the snapshot at the beginning and the transaction's own writes,
+/// which are buffered and applied atomically by `commit`.
+/// Dropping it without `commit` discards the writes.
+pub struct Transaction<K = String, V = String> {
+    snapshot: Snapshot,
+    // encoded keys and values written, where a delete has no value
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    // encoded keys read from the snapshot
+    reads: BTreeSet<Vec<u8>>,
+    codec: PhantomData<(K, V)>,
+}
+
+/// The error wrapped in `io::Error` returned by `Transaction::commit`
+/// when a key read or written by the transaction was modified by someone else since it began.
+#[derive(Debug)]
+pub struct TransactionConflict {
+    key: Vec<u8>,
+}
+
+impl TransactionConflict {
+    /// The encoded key found modified first.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+impl fmt::Display for TransactionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key {:?} was modified by another writer", self.key)
+    }
+}
+
+impl Error for TransactionConflict {}
+
+pub(crate) fn conflict(key: &[u8]) -> io::Error {
+    io::Error::other(TransactionConflict { key: key.to_vec() })
+}
+
+impl<K: Codec, V: Codec> Transaction<K, V> {
+    pub(crate) fn new(snapshot: Snapshot) -> Transaction<K, V> {
+        Transaction {
+            snapshot,
+            writes: BTreeMap::new(),
+            reads: BTreeSet::new(),
+            codec: PhantomData,
+        }
+    }
+
+    /// Reads `key`, preferring the writes of this transaction.
+    pub fn get(&mut self, sst: &SSTable<K, V>, key: &K) -> io::Result<Option<V>> {
+        let encoded = key.encode();
+        if let Some(value) = self.writes.get(&encoded) {
+            return value.as_ref().map(|value| V::decode(value)).transpose();
+        }
+        let value = self.snapshot.get(sst, key)?;
+        self.reads.insert(encoded);
+        Ok(value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.writes.insert(key.encode(), Some(value.encode()));
+    }
+
+    pub fn delete(&mut self, key: K) {
+        self.writes.insert(key.encode(), None);
+    }
+
+    /// Applies the writes atomically, or fails with `TransactionConflict` without applying any
+    /// if a key read or written was modified since the transaction began.
+    pub fn commit(mut self, sst: &SSTable<K, V>) -> io::Result<()> {
+        self.snapshot.check(sst)?;
+        // keys both read and written are checked once
+        self.reads.extend(self.writes.keys().cloned());
+        let ops = self.writes.into_iter().collect();
+        sst.write_unless_modified(
+            &self.reads,
+            self.snapshot.sequence(),
+            WriteBatch::from_ops(ops),
+        )
+    }
+}